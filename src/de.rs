@@ -1,21 +1,38 @@
 use crate::error::{Error, ParseError};
-use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use crate::options::{DuplicateKeys, PropertiesOptions};
+use crate::{INLINE_MAP_SEPARATOR, KEY_SEPARATOR};
+use serde::de::{
+    self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
 use serde::Deserialize;
-use std::io::{BufRead, BufReader, Read};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::Range;
 use std::str::FromStr;
 
 pub struct Deserializer<B: BufRead> {
     input: B,
-    current_key: Option<String>,
-    current_value: Option<String>,
-    escape: char,
-    separator: char,
+    options: PropertiesOptions,
+    /// Number of physical lines read so far.
+    line: usize,
 }
 
 pub fn from_str<'a, T: Deserialize<'a>>(s: &'a str) -> Result<T, Error> {
     from_bytes(s.as_bytes())
 }
 
+pub fn from_str_with<'a, T: Deserialize<'a>>(
+    s: &'a str,
+    options: &PropertiesOptions,
+) -> Result<T, Error> {
+    let mut deserializer = Deserializer::with_options(s.as_bytes(), options.clone());
+    T::deserialize(&mut deserializer)
+}
+
 pub fn from_bytes<'a, T: Deserialize<'a>>(b: &'a [u8]) -> Result<T, Error> {
     from_buf_read(BufReader::new(b))
 }
@@ -30,195 +47,1023 @@ pub fn from_reader<'a, T: Deserialize<'a>, R: Read + 'a>(r: R) -> Result<T, Erro
     T::deserialize(&mut deserializer)
 }
 
-impl<'de, B: BufRead> Deserializer<B> {
+impl<B: BufRead> Deserializer<B> {
     pub fn new(input: B) -> Self {
+        Deserializer::with_options(input, PropertiesOptions::default())
+    }
+
+    pub fn with_options(input: B, options: PropertiesOptions) -> Self {
         Deserializer {
-            input: input,
-            current_key: None,
-            current_value: None,
-            escape: crate::DEFAULT_ESCAPE,
-            separator: crate::DEFAULT_SEPARATOR,
+            input,
+            options,
+            line: 0,
         }
     }
 
-    fn parse_line<'b>(&mut self, l: &'b str) -> Result<(&'b str, &'b str), ParseError> {
-        let mut key: Option<usize> = None;
-        let mut value: Option<usize> = None;
-        let mut escaped = false;
-        for c in l.chars() {
-            if !escaped && c == self.escape {
-                escaped = true;
+    /// Reads the whole input, nesting dotted keys by segment.
+    fn read_tree(&mut self) -> Result<Node, Error> {
+        let mut root = Node::default();
+        while let Some(line) = self.read_line()? {
+            let (key, value, position) = self.parse_line(&line)?;
+            let policy = if self.options.repeat_keys {
+                DuplicateKeys::Collect
             } else {
-                if !escaped && c == self.separator {
-                    if key.is_none() {
-                        return Err(ParseError::NoKey);
-                    }
-                    value = Some(key.unwrap() + 1);
-                    break;
+                self.options.duplicate_keys
+            };
+            if let Err(first_line) = root.insert(&key, value, line.position(0), position, policy) {
+                let (line, column) = line.position(0);
+                return Err(Error::Located {
+                    line,
+                    column,
+                    key: Some(key.text),
+                    error: Box::new(ParseError::DuplicateKey { first_line }.into()),
+                });
+            }
+        }
+        Ok(root)
+    }
+
+    /// Reads the next logical line holding a key/value pair, skipping blank
+    /// lines and comment lines starting with `#` or `!`.
+    ///
+    /// A line ending in an odd number of escape characters continues on the
+    /// next physical line, whose leading whitespace is dropped.
+    fn read_line(&mut self) -> Result<Option<Line>, Error> {
+        let mut line = Line::default();
+        loop {
+            let mut buf = String::new();
+            let read =
+                read_physical_line(&mut self.input, &mut buf).map_err(|err| Error::Located {
+                    line: self.line + 1,
+                    column: 1,
+                    key: None,
+                    error: Box::new(err.into()),
+                })?;
+            if read == 0 {
+                return Ok(if line.starts.is_empty() {
+                    None
                 } else {
-                    if let Some(key) = key.as_mut() {
-                        *key += c.len_utf8();
-                    } else {
-                        key = Some(c.len_utf8());
-                    }
+                    Some(line)
+                });
+            }
+            self.line += 1;
+            let content = buf.trim_end_matches(['\n', '\r']);
+            let physical = content.trim_start_matches(is_whitespace);
+            if line.starts.is_empty() {
+                match physical.chars().next() {
+                    None | Some('#') | Some('!') => continue,
+                    _ => {}
                 }
             }
+            let indent = content[..content.len() - physical.len()].chars().count();
+            line.starts.push((line.text.len(), self.line, indent + 1));
+            let escapes = physical
+                .chars()
+                .rev()
+                .take_while(|&c| c == self.options.escape)
+                .count();
+            if escapes % 2 == 1 {
+                line.text
+                    .push_str(&physical[..physical.len() - self.options.escape.len_utf8()]);
+            } else {
+                line.text.push_str(physical);
+                return Ok(Some(line));
+            }
+        }
+    }
+
+    /// Splits a logical line into its key and value, returning the position
+    /// the value starts at.
+    ///
+    /// The key ends at the first unescaped `=`, `:`, configured separator or
+    /// whitespace. Whitespace around the separator is skipped, and a key with
+    /// no separator at all has an empty value, as a separator with nothing
+    /// before it has an empty key. The key is returned split into its dotted
+    /// segments.
+    fn parse_line(&self, line: &Line) -> Result<(Key, String, Position), Error> {
+        let l = line.text.trim_start_matches(is_whitespace);
+        let start = line.text.len() - l.len();
+        let mut key_end = l.len();
+        let mut escaped = false;
+        for (i, c) in l.char_indices() {
             if escaped {
                 escaped = false;
+            } else if c == self.options.escape {
+                escaped = true;
+            } else if self.is_separator(c) || is_whitespace(c) {
+                key_end = i;
+                break;
+            }
+        }
+        let key = &l[..key_end];
+        let split = self.split_key(key).map_err(|offset| {
+            line.error(start + offset, Some(key), ParseError::InvalidUnicodeEscape)
+        })?;
+        let mut has_separator = false;
+        let mut value_start = key_end;
+        for c in l[key_end..].chars() {
+            if self.is_separator(c) && !has_separator {
+                has_separator = true;
+            } else if !is_whitespace(c) {
+                break;
             }
+            value_start += c.len_utf8();
         }
-        if value.is_none() {
-            return Err(ParseError::NoValue);
+        let mut value = &l[value_start..];
+        if self.options.trim_values {
+            value = trim_end(value, self.options.escape);
         }
-        unsafe {
-            Ok((
-                l.get_unchecked(..key.unwrap()).trim(),
-                l.get_unchecked(value.unwrap()..).trim(),
-            ))
+        // Values are kept escaped so lists can be split on unescaped
+        // delimiters, but invalid escapes are reported here.
+        unescape(value, self.options.escape).map_err(|offset| {
+            line.error(
+                start + value_start + offset,
+                Some(key),
+                ParseError::InvalidUnicodeEscape,
+            )
+        })?;
+        Ok((split, value.to_string(), line.position(start + value_start)))
+    }
+
+    /// Splits a key on unescaped dots, unescaping each segment. A bracketed
+    /// index such as `[0]` is a segment of its own, so `servers[0].host` and
+    /// `servers.0.host` are the same key. Brackets holding anything but
+    /// digits are part of the segment.
+    ///
+    /// Fails with the offset of an invalid escape sequence.
+    fn split_key(&self, key: &str) -> Result<Key, usize> {
+        let mut split = Key::default();
+        let segment = |split: &mut Key, start: usize, end: usize| -> Result<(), usize> {
+            let unescaped =
+                unescape(&key[start..end], self.options.escape).map_err(|offset| start + offset)?;
+            let begin = split.text.len();
+            split.text.push_str(&unescaped);
+            split.segments.push(begin..split.text.len());
+            Ok(())
+        };
+        let mut start = 0;
+        let mut escaped = false;
+        // Set right after a `[index]`, which needs no dot before the next segment.
+        let mut bracketed = false;
+        let mut skip_to = 0;
+        for (i, c) in key.char_indices() {
+            if i < skip_to {
+                continue;
+            } else if escaped {
+                escaped = false;
+            } else if c == self.options.escape {
+                escaped = true;
+            } else if c == KEY_SEPARATOR {
+                if !(bracketed && start == i) {
+                    segment(&mut split, start, i)?;
+                }
+                split.text.push(c);
+                start = i + c.len_utf8();
+                bracketed = false;
+            } else if c == '[' {
+                let index = key[i + 1..]
+                    .find(']')
+                    .map(|len| &key[i + 1..i + 1 + len])
+                    .filter(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()));
+                if let Some(index) = index {
+                    if !(bracketed && start == i) {
+                        segment(&mut split, start, i)?;
+                    }
+                    split.text.push('[');
+                    segment(&mut split, i + 1, i + 1 + index.len())?;
+                    split.text.push(']');
+                    start = i + index.len() + 2;
+                    skip_to = start;
+                    bracketed = true;
+                }
+            }
+        }
+        if !(bracketed && start == key.len()) {
+            segment(&mut split, start, key.len())?;
+        }
+        Ok(split)
+    }
+
+    fn is_separator(&self, c: char) -> bool {
+        c == '=' || c == ':' || c == self.options.separator
+    }
+}
+
+/// Decodes the escape sequences understood by `java.util.Properties`:
+/// `\t`, `\n`, `\r`, `\f` and `\uXXXX`, where a pair of `\u` escapes may
+/// encode a UTF-16 surrogate pair. Any other escaped character stands for
+/// itself.
+///
+/// Fails with the offset of an invalid `\u` escape.
+fn unescape(s: &str, escape: char) -> Result<String, usize> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    loop {
+        let offset = s.len() - chars.as_str().len();
+        let c = match chars.next() {
+            Some(c) => c,
+            None => break,
+        };
+        if c != escape {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('f') => unescaped.push('\x0c'),
+            Some('u') => {
+                let c = read_unicode_escape(&mut chars, escape).ok_or(offset)?;
+                unescaped.push(c);
+            }
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    Ok(unescaped)
+}
+
+/// Trims trailing whitespace that isn't escaped.
+fn trim_end(value: &str, escape: char) -> &str {
+    let mut end = value.len();
+    while let Some(c) = value[..end].chars().next_back() {
+        if !is_whitespace(c) {
+            break;
+        }
+        let escapes = value[..end - c.len_utf8()]
+            .chars()
+            .rev()
+            .take_while(|&e| e == escape)
+            .count();
+        if escapes % 2 == 1 {
+            break;
+        }
+        end -= c.len_utf8();
+    }
+    &value[..end]
+}
+
+/// Trims leading whitespace, and trailing whitespace that isn't escaped.
+fn trim(value: &str, escape: char) -> &str {
+    trim_end(value.trim_start_matches(is_whitespace), escape)
+}
+
+/// Whether `value` is wrapped in a pair of unescaped brackets, as in
+/// `[a, b]` but not `[a];[b]`.
+fn is_bracketed(value: &str, escape: char) -> bool {
+    if !value.starts_with('[') || !value.ends_with(']') {
+        return false;
+    }
+    let mut depth = 0;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == escape {
+            escaped = true;
+        } else if c == '[' {
+            depth += 1;
+        } else if c == ']' {
+            depth -= 1;
+            if depth == 0 {
+                return i == value.len() - 1;
+            }
+        }
+    }
+    false
+}
+
+/// Splits an escaped inline map entry at its first unescaped `:`, trimming
+/// whitespace around the key and value.
+fn split_entry(entry: &str, escape: char) -> Option<(&str, &str)> {
+    let mut escaped = false;
+    for (i, c) in entry.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == escape {
+            escaped = true;
+        } else if c == INLINE_MAP_SEPARATOR {
+            let value = &entry[i + c.len_utf8()..];
+            return Some((trim(&entry[..i], escape), trim(value, escape)));
+        }
+    }
+    None
+}
+
+/// Splits an escaped list value on unescaped delimiters, trimming whitespace
+/// around each element. The list may be wrapped in brackets, as in
+/// `[a, b]`, and is empty if there's nothing but whitespace within.
+fn split_list<'v>(value: &'v str, delimiter: &str, escape: char) -> Vec<&'v str> {
+    let mut value = trim(value, escape);
+    if is_bracketed(value, escape) {
+        value = trim(&value[1..value.len() - 1], escape);
+    }
+    if value.is_empty() {
+        return Vec::new();
+    }
+    let mut elements = Vec::new();
+    let mut start = 0;
+    let mut chars = value.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == escape {
+            // The whole escape sequence is skipped, so a delimiter made of
+            // hex digits can't split a `\uXXXX`.
+            if let Some((_, 'u')) = chars.next() {
+                chars.nth(3);
+            }
+        } else if !delimiter.is_empty() && value[i..].starts_with(delimiter) {
+            elements.push(trim(&value[start..i], escape));
+            start = i + delimiter.len();
+            while value.len() - chars.as_str().len() < start {
+                chars.next();
+            }
+        }
+    }
+    elements.push(trim(&value[start..], escape));
+    elements
+}
+
+/// Reads a `\uXXXX` escape after the `\u`, and the low surrogate that must
+/// follow a high surrogate.
+fn read_unicode_escape(chars: &mut std::str::Chars, escape: char) -> Option<char> {
+    let unit = read_utf16_unit(chars)?;
+    let code = if (0xD800..0xDC00).contains(&unit) {
+        if chars.next() != Some(escape) || chars.next() != Some('u') {
+            return None;
+        }
+        let low = read_utf16_unit(chars)?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return None;
+        }
+        0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00)
+    } else {
+        unit
+    };
+    char::from_u32(code)
+}
+
+/// Reads the four hex digits of a `\uXXXX` escape.
+fn read_utf16_unit(chars: &mut std::str::Chars) -> Option<u32> {
+    let mut unit = 0;
+    for _ in 0..4 {
+        unit = unit * 16 + chars.next()?.to_digit(16)?;
+    }
+    Some(unit)
+}
+
+/// Line and column in the input, both starting at 1.
+type Position = (usize, usize);
+
+/// A logical line, made of one or more physical lines.
+#[derive(Default)]
+struct Line {
+    text: String,
+    /// Where each physical line starts in `text`, with its line number and
+    /// the column its text starts at.
+    starts: Vec<(usize, usize, usize)>,
+}
+
+impl Line {
+    /// Returns the line and column of a byte offset into `text`.
+    fn position(&self, offset: usize) -> Position {
+        let (start, line, column) = self
+            .starts
+            .iter()
+            .rev()
+            .find(|(start, _, _)| *start <= offset)
+            .copied()
+            .unwrap_or((0, 0, 1));
+        (line, column + self.text[start..offset].chars().count())
+    }
+
+    fn error(&self, offset: usize, key: Option<&str>, err: ParseError) -> Error {
+        let (line, column) = self.position(offset);
+        Error::Located {
+            line,
+            column,
+            key: key.map(str::to_string),
+            error: Box::new(err.into()),
+        }
+    }
+}
+
+/// Reads a physical line into `buf` like `BufRead::read_line`, but ending it at
+/// a bare `\r` as well as at `\n` or `\r\n`, as `java.util.Properties` does.
+fn read_physical_line<B: BufRead>(input: &mut B, buf: &mut String) -> io::Result<usize> {
+    let mut bytes = Vec::new();
+    loop {
+        let available = match input.fill_buf() {
+            Ok(available) => available,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if available.is_empty() {
+            break;
+        }
+        match available.iter().position(|&b| b == b'\n' || b == b'\r') {
+            Some(i) => {
+                let cr = available[i] == b'\r';
+                bytes.extend_from_slice(&available[..=i]);
+                input.consume(i + 1);
+                if cr && input.fill_buf()?.first() == Some(&b'\n') {
+                    bytes.push(b'\n');
+                    input.consume(1);
+                }
+                break;
+            }
+            None => {
+                let len = available.len();
+                bytes.extend_from_slice(available);
+                input.consume(len);
+            }
+        }
+    }
+    let read = bytes.len();
+    let line =
+        String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    buf.push_str(&line);
+    Ok(read)
+}
+
+/// Whitespace as defined by `java.util.Properties`: space, tab and form feed.
+fn is_whitespace(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\x0c'
+}
+
+/// A key as read from the input, unescaped, with the span of each of its
+/// dotted segments.
+#[derive(Clone, Debug, Default)]
+struct Key {
+    text: String,
+    segments: Vec<Range<usize>>,
+}
+
+impl Key {
+    fn segments(&self) -> impl Iterator<Item = &str> {
+        self.segments
+            .iter()
+            .map(move |range| &self.text[range.clone()])
+    }
+
+    /// Returns the key as written from segment `i` on.
+    fn suffix(&self, i: usize) -> String {
+        let range = &self.segments[i];
+        // Only an index segment starts right after a `[`, which is dropped
+        // along with its `]`.
+        if range.start > 0 && self.text.as_bytes()[range.start - 1] == b'[' {
+            format!(
+                "{}{}",
+                &self.text[range.clone()],
+                &self.text[range.end + 1..]
+            )
+        } else {
+            self.text[range.start..].to_string()
+        }
+    }
+}
+
+/// Keys and values read from the input, with dotted keys nested by
+/// segment.
+#[derive(Debug, Default)]
+struct Node {
+    value: Option<String>,
+    /// Values assigned before `value`, kept with `DuplicateKeys::Collect`.
+    previous: Vec<String>,
+    /// The key `value` was last set with.
+    key: Key,
+    children: Vec<(String, Node)>,
+    /// Position of each child in `children` by segment, so inserting stays
+    /// constant time while `children` keeps the input order.
+    index: HashMap<String, usize>,
+    /// Line and column of the value, or of the key that first nested keys
+    /// below this node. Zero for the root.
+    position: Position,
+}
+
+impl Node {
+    /// Sets the value at `key`, failing with the line the key was first set
+    /// on if `policy` doesn't allow setting it again.
+    fn insert(
+        &mut self,
+        key: &Key,
+        value: String,
+        key_position: Position,
+        value_position: Position,
+        policy: DuplicateKeys,
+    ) -> Result<(), usize> {
+        let mut node = self;
+        for segment in key.segments() {
+            node = node.child(segment, key_position);
+        }
+        if let Some(previous) = node.value.take() {
+            match policy {
+                DuplicateKeys::LastWins => {}
+                DuplicateKeys::FirstWins => {
+                    node.value = Some(previous);
+                    return Ok(());
+                }
+                DuplicateKeys::Error => {
+                    node.value = Some(previous);
+                    return Err(node.position.0);
+                }
+                DuplicateKeys::Collect => node.previous.push(previous),
+            }
         }
+        node.value = Some(value);
+        node.key = key.clone();
+        node.position = value_position;
+        Ok(())
+    }
+
+    /// Returns the child at `segment`, adding it if it's new.
+    fn child(&mut self, segment: &str, position: Position) -> &mut Node {
+        let index = match self.index.get(segment) {
+            Some(&index) => index,
+            None => {
+                let node = Node {
+                    position,
+                    ..Node::default()
+                };
+                self.index.insert(segment.to_string(), self.children.len());
+                self.children.push((segment.to_string(), node));
+                self.children.len() - 1
+            }
+        };
+        &mut self.children[index].1
     }
+}
+
+/// Returns the first value found below `children`, depth first.
+fn first_leaf(children: &[(String, Node)]) -> Option<&str> {
+    children
+        .iter()
+        .find_map(|(_, node)| node.value.as_deref().or_else(|| first_leaf(&node.children)))
+}
 
-    fn parse<U: FromStr>(value: &str) -> Option<U> {
-        value.parse().map(|v| Some(v)).unwrap_or(None)
+/// Parses a decimal float, leaving words such as `inf` or `NaN` as strings.
+fn parse_float(value: &str) -> Option<f64> {
+    if value.bytes().any(|b| b.is_ascii_digit())
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_digit() || matches!(b, b'+' | b'-' | b'.' | b'e' | b'E'))
+    {
+        value.parse().ok()
+    } else {
+        None
     }
+}
+
+/// Whether `children` are the elements of a sequence written as indexed
+/// keys, such as `servers[0]` and `servers[1]`.
+fn is_indexed(children: &[(String, Node)]) -> bool {
+    !children.is_empty() && children.iter().all(|(k, _)| is_index(k))
+}
+
+/// Whether `key` is an index: ASCII digits only, as `parse::<usize>` alone
+/// would accept a sign, as in `+1`.
+fn is_index(key: &str) -> bool {
+    !key.is_empty() && key.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Whether `children` are indices counting up from zero, as
+/// `indexed_elements` requires, rather than a map that happens to have
+/// numeric keys, such as `ports.80`.
+fn counts_up(children: &[(String, Node)]) -> bool {
+    is_indexed(children)
+        && children
+            .iter()
+            .enumerate()
+            .all(|(expected, (index, _))| index.parse() == Ok(expected))
+}
 
-    fn deserialize<T: FromStr>(v: Option<&str>) -> Result<T, Error> {
-        Deserializer::<B>::parse(v.as_ref().ok_or(ParseError::NoValue)?)
-            .ok_or(Error::Parse(ParseError::InvalidValue))
+/// Collects every value below `children`, `depth` levels below the keys of
+/// a map, in the same order as `first_leaf`. Each value comes with its key as
+/// written, from the map's level on.
+fn collect_leaves<'a>(
+    children: &'a [(String, Node)],
+    depth: usize,
+    leaves: &mut VecDeque<(String, &'a Node)>,
+) {
+    for (_, node) in children {
+        if node.value.is_some() {
+            let key = node.key.suffix(node.key.segments.len() - 1 - depth);
+            leaves.push_back((key, node));
+        }
+        collect_leaves(&node.children, depth + 1, leaves);
     }
 }
 
+macro_rules! forward_to_tree {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V: Visitor<'de>>(
+                self,
+                $($arg: $ty,)*
+                visitor: V,
+            ) -> Result<V::Value, Self::Error> {
+                let root = self.read_tree()?;
+                NodeDeserializer::new(&root, &self.options).$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
 impl<'de, B: BufRead> de::Deserializer<'de> for &mut Deserializer<B> {
     type Error = Error;
-    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        if self.current_key.is_some() {
-            self.deserialize_str(visitor)
-        } else if let Some(value) = self.current_value.as_ref() {
-            if let Some(v) = Deserializer::<B>::parse(value) {
-                visitor.visit_bool(v)
-            } else if let Some(v) = Deserializer::<B>::parse(value) {
-                visitor.visit_u64(v)
-            } else if let Some(v) = Deserializer::<B>::parse(value) {
-                visitor.visit_i64(v)
-            } else if value.is_empty() {
-                self.deserialize_unit(visitor)
-            } else {
-                self.deserialize_str(visitor)
+
+    forward_to_tree! {
+        deserialize_any()
+        deserialize_bool()
+        deserialize_i8()
+        deserialize_i16()
+        deserialize_i32()
+        deserialize_i64()
+        deserialize_i128()
+        deserialize_u8()
+        deserialize_u16()
+        deserialize_u32()
+        deserialize_u64()
+        deserialize_u128()
+        deserialize_f32()
+        deserialize_f64()
+        deserialize_char()
+        deserialize_str()
+        deserialize_string()
+        deserialize_bytes()
+        deserialize_byte_buf()
+        deserialize_option()
+        deserialize_unit()
+        deserialize_unit_struct(name: &'static str)
+        deserialize_newtype_struct(name: &'static str)
+        deserialize_seq()
+        deserialize_tuple(len: usize)
+        deserialize_tuple_struct(name: &'static str, len: usize)
+        deserialize_map()
+        deserialize_struct(name: &'static str, fields: &'static [&'static str])
+        deserialize_enum(name: &'static str, variants: &'static [&'static str])
+        deserialize_identifier()
+        deserialize_ignored_any()
+    }
+}
+
+/// The key of a node being deserialized, linked to its parent's so errors
+/// can name the full key without building it up front.
+struct KeyPath<'a> {
+    parent: Option<&'a KeyPath<'a>>,
+    segment: Segment<'a>,
+}
+
+enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+impl<'a> KeyPath<'a> {
+    fn new(parent: Option<&'a KeyPath<'a>>, segment: Segment<'a>) -> Self {
+        KeyPath { parent, segment }
+    }
+}
+
+impl fmt::Display for KeyPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(parent) = self.parent {
+            write!(f, "{}", parent)?;
+        }
+        match self.segment {
+            Segment::Key(key) if self.parent.is_some() => write!(f, "{}{}", KEY_SEPARATOR, key),
+            Segment::Key(key) => f.write_str(key),
+            Segment::Index(index) => write!(f, "[{}]", index),
+        }
+    }
+}
+
+/// Deserializes a single node of the key tree: its own value, the keys
+/// nested below it, or both.
+#[derive(Clone, Copy)]
+struct NodeDeserializer<'a> {
+    value: Option<&'a str>,
+    previous: &'a [String],
+    /// Whether `value` is still escaped as read from the input, rather than
+    /// an already unescaped map key, which is never inferred to be another
+    /// type than a string.
+    escaped: bool,
+    children: &'a [(String, Node)],
+    options: &'a PropertiesOptions,
+    key: Option<&'a KeyPath<'a>>,
+    position: Position,
+    /// Set when a map value is being deserialized, and raised if the value
+    /// turns out to be a scalar so the map switches to full dotted keys. A
+    /// node with no value of its own then yields the first value below it.
+    flatten: Option<&'a Cell<bool>>,
+    /// Set on the first element of a sequence that may be split on the
+    /// secondary delimiter: the first group of the sequence, read instead of
+    /// `value` if the element is a sequence itself, and raised if so.
+    group: Option<(&'a str, &'a Cell<bool>)>,
+}
+
+impl<'a> NodeDeserializer<'a> {
+    fn new(node: &'a Node, options: &'a PropertiesOptions) -> Self {
+        NodeDeserializer {
+            value: node.value.as_deref(),
+            previous: &node.previous,
+            escaped: true,
+            children: &node.children,
+            options,
+            key: None,
+            position: node.position,
+            flatten: None,
+            group: None,
+        }
+    }
+
+    fn leaf(value: &'a str, options: &'a PropertiesOptions) -> Self {
+        NodeDeserializer {
+            value: Some(value),
+            previous: &[],
+            escaped: false,
+            children: &[],
+            options,
+            key: None,
+            position: (0, 0),
+            flatten: None,
+            group: None,
+        }
+    }
+
+    /// Returns this node's own value, without the keys nested below it.
+    fn leaf_of(node: &'a Node, options: &'a PropertiesOptions) -> Self {
+        NodeDeserializer {
+            children: &[],
+            ..NodeDeserializer::new(node, options)
+        }
+    }
+
+    /// Adds the position and key of this node to an error raised while
+    /// deserializing it, unless it was raised deeper and already has them.
+    fn locate(&self, err: Error) -> Error {
+        match (err, self.position) {
+            (err @ Error::Located { .. }, _) | (err, (0, _)) => err,
+            (err, (line, column)) => Error::Located {
+                line,
+                column,
+                key: self.key.map(|key| key.to_string()),
+                error: Box::new(err),
+            },
+        }
+    }
+
+    /// Returns this node as found under `key`.
+    fn at<'b>(self, key: &'b KeyPath<'b>) -> NodeDeserializer<'b>
+    where
+        'a: 'b,
+    {
+        NodeDeserializer {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    /// Returns this node's value as written, before unescaping.
+    fn raw(&self) -> Result<&'a str, Error> {
+        if let (Some(flatten), false) = (self.flatten, self.children.is_empty()) {
+            flatten.set(true);
+            if self.value.is_none() {
+                return first_leaf(self.children).ok_or_else(|| ParseError::NoValue.into());
+            }
+        }
+        self.value.ok_or_else(|| ParseError::NoValue.into())
+    }
+
+    fn value(&self) -> Result<Cow<'a, str>, Error> {
+        self.raw().map(|value| self.unescape(value))
+    }
+
+    fn unescape(&self, value: &'a str) -> Cow<'a, str> {
+        let escape = self.options.escape;
+        if !self.escaped || !value.contains(escape) {
+            return Cow::Borrowed(value);
+        }
+        // Escapes were checked when the input was read.
+        unescape(value, escape).map_or(Cow::Borrowed(value), Cow::Owned)
+    }
+
+    /// Returns the elements of a sequence, written either as indexed keys or
+    /// as delimited values. See `split_list` for the grammar of the latter.
+    fn elements(&self) -> Result<Vec<NodeDeserializer<'a>>, Error> {
+        if is_indexed(self.children) {
+            return self.indexed_elements();
+        }
+        self.split(&self.options.list_delimiter)
+    }
+
+    /// Returns the elements of a sequence of sequences split on the secondary
+    /// delimiter, if one is set and the sequence is a delimited value.
+    fn groups(&self) -> Result<Option<Vec<NodeDeserializer<'a>>>, Error> {
+        match &self.options.secondary_delimiter {
+            Some(delimiter) if !is_indexed(self.children) => self.split(delimiter).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Splits the values of this node on `delimiter`, the earlier values of a
+    /// repeated key first.
+    fn split(&self, delimiter: &str) -> Result<Vec<NodeDeserializer<'a>>, Error> {
+        let node = *self;
+        let escape = self.options.escape;
+        Ok(self
+            .previous
+            .iter()
+            .map(String::as_str)
+            .chain(Some(self.raw()?))
+            .flat_map(|value| split_list(value, delimiter, escape))
+            .map(|value| NodeDeserializer {
+                value: Some(value),
+                previous: &[],
+                children: &[],
+                flatten: None,
+                group: None,
+                ..node
+            })
+            .collect())
+    }
+
+    /// Switches the first element of a sequence to its group of the
+    /// sequence split on the secondary delimiter, now that it turned out to
+    /// be a sequence itself.
+    fn grouped(self) -> Self {
+        match self.group {
+            Some((group, nested)) => {
+                nested.set(true);
+                NodeDeserializer {
+                    value: Some(group),
+                    group: None,
+                    ..self
+                }
             }
+            None => self,
+        }
+    }
+
+    /// Returns the elements of a sequence written as indexed keys, which must
+    /// count up from zero.
+    fn indexed_elements(&self) -> Result<Vec<NodeDeserializer<'a>>, Error> {
+        self.children
+            .iter()
+            .enumerate()
+            .map(|(expected, (index, node))| {
+                if index.parse() != Ok(expected) {
+                    return Err(Error::Custom(format!(
+                        "Expected index {} but found index {}",
+                        expected, index
+                    )));
+                }
+                Ok(NodeDeserializer::new(node, self.options))
+            })
+            .collect()
+    }
+
+    fn bytes(&self) -> Result<Vec<u8>, Error> {
+        let encoding = self.options.binary_encoding;
+        encoding.decode(&self.value()?).map_err(|reason| {
+            Error::Custom(format!("Invalid {} value: {}", encoding.name(), reason))
+        })
+    }
+
+    fn parse<T: FromStr>(&self) -> Result<T, Error> {
+        let value = self.value()?;
+        value.parse().map_err(|_| Error::InvalidValue {
+            value: value.into_owned(),
+            expected: std::any::type_name::<T>(),
+        })
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for NodeDeserializer<'a> {
+    type Error = Error;
+
+    /// A group of nested keys is visited as a map, or as a sequence when its
+    /// keys are indices counting up from zero, so serde can buffer it for
+    /// tagged enums.
+    ///
+    /// Values are untyped text, so unless `infer_types` is disabled a value is
+    /// visited as unit when empty, then as the first of `bool`, `u64`, `i64`,
+    /// `f64` it parses as, and as a string otherwise. Map keys are always
+    /// visited as strings, so `ports.80` reads as a map with the key `"80"`.
+    /// This is what `#[serde(flatten)]` and `#[serde(untagged)]` fields see.
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let value = match self.value {
+            None if counts_up(self.children) => return self.deserialize_seq(visitor),
+            None => return self.deserialize_map(visitor),
+            Some(_) if !self.previous.is_empty() => return self.deserialize_seq(visitor),
+            Some(value) => self.unescape(value),
+        };
+        let value = &*value;
+        if !self.options.infer_types || !self.escaped {
+            visitor.visit_str(value)
+        } else if value.is_empty() {
+            visitor.visit_unit()
+        } else if let Ok(v) = value.parse() {
+            visitor.visit_bool(v)
+        } else if let Ok(v) = value.parse() {
+            visitor.visit_u64(v)
+        } else if let Ok(v) = value.parse() {
+            visitor.visit_i64(v)
+        } else if let Some(v) = parse_float(value) {
+            visitor.visit_f64(v)
         } else {
-            Err(Error::Parse(ParseError::NoValue))
+            visitor.visit_str(value)
         }
     }
 
     fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_bool(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_bool(self.parse()?)
     }
 
     fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_i8(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_i8(self.parse()?)
     }
 
     fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_i16(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_i16(self.parse()?)
     }
 
     fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_i32(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_i32(self.parse()?)
     }
 
     fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_i64(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_i64(self.parse()?)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_i128(self.parse()?)
     }
 
     fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_u8(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_u8(self.parse()?)
     }
 
     fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_u16(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_u16(self.parse()?)
     }
 
     fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_u32(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_u32(self.parse()?)
     }
 
     fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_u64(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_u64(self.parse()?)
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_u128(self.parse()?)
     }
 
     fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_f32(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_f32(self.parse()?)
     }
 
     fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_f64(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_f64(self.parse()?)
     }
 
     fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        visitor.visit_char(Deserializer::<B>::deserialize(
-            self.current_value.as_ref().map(|x| &**x),
-        )?)
+        visitor.visit_char(self.parse()?)
     }
 
     fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        if let Some(key) = self.current_key.as_ref() {
-            visitor.visit_str(key)
-        } else if let Some(value) = self.current_value.as_ref() {
-            visitor.visit_str(value)
-        } else {
-            Err(Error::Custom("No key or value".to_string()))
-        }
+        visitor.visit_str(&self.value()?)
     }
 
     fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
         self.deserialize_str(visitor)
     }
 
-    fn deserialize_bytes<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
+    /// Bytes are decoded from the value with the configured `BinaryEncoding`.
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_byte_buf(self.bytes()?)
     }
 
-    fn deserialize_byte_buf<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_byte_buf(self.bytes()?)
     }
 
     fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        let v = self.current_value.as_ref().ok_or(ParseError::NoValue)?;
-        if v.is_empty() {
-            visitor.visit_none()
-        } else {
-            visitor.visit_some(self)
+        match self.value {
+            Some("") => visitor.visit_none(),
+            None if self.children.is_empty() => visitor.visit_none(),
+            _ => visitor.visit_some(self),
         }
     }
 
     fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        let v = self.current_value.as_ref().ok_or(ParseError::NoValue)?;
-        if !v.is_empty() {
-            Err(Error::Parse(ParseError::InvalidValue))
-        } else {
-            visitor.visit_unit()
+        match &*self.value()? {
+            "" => visitor.visit_unit(),
+            value => Err(Error::InvalidValue {
+                value: value.to_string(),
+                expected: "()",
+            }),
         }
     }
 
@@ -238,34 +1083,38 @@ impl<'de, B: BufRead> de::Deserializer<'de> for &mut Deserializer<B> {
         visitor.visit_newtype_struct(self)
     }
 
-    fn deserialize_seq<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Self::Error> {
-        
-        let values = self.current_value.as_ref().ok_or(ParseError::NoValue)?.split(",").map(|s|s.to_string()).collect::<Vec<String>>();
-        visitor.visit_seq(SeqDeserializer::new(&mut self, values))
+    /// Sequences are read from indexed keys, as in `hosts[0]=a`, or from a
+    /// delimited value, as in `hosts=a, b` or `hosts=[a, b]`.
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(SeqDeserializer::new(self.grouped(), None)?)
     }
 
+    /// Tuples are read like sequences, as in `point=1,2`, but must have
+    /// exactly `len` elements.
     fn deserialize_tuple<V: Visitor<'de>>(
         self,
-        _len: usize,
-        _visitor: V,
+        len: usize,
+        visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        unimplemented!()
+        visitor.visit_seq(SeqDeserializer::new(self.grouped(), Some(len))?)
     }
 
     fn deserialize_tuple_struct<V: Visitor<'de>>(
         self,
         _name: &'static str,
-        _len: usize,
-        _visitor: V,
+        len: usize,
+        visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        unimplemented!()
+        self.deserialize_tuple(len, visitor)
     }
 
-    fn deserialize_map<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Self::Error> {
-        if self.current_value.is_some() || self.current_key.is_some() {
-            return Err(Error::Custom("Nested maps or structs not supported".to_string()));
+    /// Maps are read from nested keys, or from a single value of `key:value`
+    /// entries separated by the list delimiter, as in `weights=a:1,b:2`.
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.value.is_some() && self.children.is_empty() {
+            return visitor.visit_map(InlineMapDeserializer::new(self)?);
         }
-        visitor.visit_map(&mut self)
+        visitor.visit_map(MapDeserializer::new(self.children, self.options, self.key))
     }
 
     fn deserialize_struct<V: Visitor<'de>>(
@@ -277,13 +1126,47 @@ impl<'de, B: BufRead> de::Deserializer<'de> for &mut Deserializer<B> {
         self.deserialize_map(visitor)
     }
 
+    /// A unit variant is read from the value, as in `level=Debug`. Any other
+    /// variant is the single key nested below, holding the variant's data, as
+    /// in `transport.Tcp.port=8080`.
     fn deserialize_enum<V: Visitor<'de>>(
         self,
         _name: &'static str,
-        _variants: &'static [&'static str],
-        _visitor: V,
+        variants: &'static [&'static str],
+        visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        unimplemented!()
+        let variant = |name: Cow<'a, str>| {
+            if self.options.case_insensitive_enums {
+                variants
+                    .iter()
+                    .find(|variant| variant.eq_ignore_ascii_case(&name))
+                    .map_or(name, |variant| Cow::Borrowed(*variant))
+            } else {
+                name
+            }
+        };
+        if self.value.is_some() {
+            return visitor.visit_enum(EnumDeserializer::new(variant(self.value()?), None));
+        }
+        match self.children {
+            [(name, node)]
+                if self.flatten.is_none() || variants.contains(&&*variant(Cow::Borrowed(name))) =>
+            {
+                visitor.visit_enum(EnumDeserializer::new(
+                    variant(Cow::Borrowed(name)),
+                    Some(NodeDeserializer {
+                        key: self.key,
+                        ..NodeDeserializer::new(node, self.options)
+                    }),
+                ))
+            }
+            _ if self.flatten.is_some() => {
+                visitor.visit_enum(EnumDeserializer::new(variant(self.value()?), None))
+            }
+            _ => Err(Error::Custom(
+                "Expected an enum variant as a value or a single nested key".to_string(),
+            )),
+        }
     }
 
     fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
@@ -291,77 +1174,375 @@ impl<'de, B: BufRead> de::Deserializer<'de> for &mut Deserializer<B> {
     }
 
     fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        self.deserialize_any(visitor)
+        visitor.visit_unit()
+    }
+}
+
+/// Iterates the keys nested directly below a node.
+///
+/// Maps with scalar values can't be told apart from maps of structs until a
+/// value is requested, so keys start out grouped by their next segment. Once
+/// a map value turns out to be a scalar, the map switches to full dotted keys,
+/// so `a.b=1` reads into a `HashMap<String, String>` as `"a.b"`.
+struct MapDeserializer<'a> {
+    children: std::slice::Iter<'a, (String, Node)>,
+    leaves: VecDeque<(String, &'a Node)>,
+    value: Option<(Cow<'a, str>, NodeDeserializer<'a>)>,
+    options: &'a PropertiesOptions,
+    key: Option<&'a KeyPath<'a>>,
+    flat: bool,
+}
+
+impl<'a> MapDeserializer<'a> {
+    fn new(
+        children: &'a [(String, Node)],
+        options: &'a PropertiesOptions,
+        key: Option<&'a KeyPath<'a>>,
+    ) -> Self {
+        MapDeserializer {
+            children: children.iter(),
+            leaves: VecDeque::new(),
+            value: None,
+            options,
+            key,
+            flat: false,
+        }
+    }
+
+    fn next_entry(&mut self) -> Option<(Cow<'a, str>, NodeDeserializer<'a>)> {
+        if let Some((key, node)) = self.leaves.pop_front() {
+            return Some((
+                Cow::Owned(key),
+                NodeDeserializer::leaf_of(node, self.options),
+            ));
+        }
+        let (key, node) = self.children.next()?;
+        if self.flat && !node.children.is_empty() {
+            if node.value.is_some() {
+                self.leaves.push_back((key.clone(), node));
+            }
+            collect_leaves(&node.children, 1, &mut self.leaves);
+            return self.next_entry();
+        }
+        Some((
+            Cow::Borrowed(key),
+            NodeDeserializer::new(node, self.options),
+        ))
     }
 }
 
-impl<'de, B: BufRead> MapAccess<'de> for &mut Deserializer<B> {
+impl<'de, 'a> MapAccess<'de> for MapDeserializer<'a> {
     type Error = Error;
 
     fn next_key_seed<K: DeserializeSeed<'de>>(
         &mut self,
         seed: K,
     ) -> Result<Option<K::Value>, Self::Error> {
-        let mut buf = Box::new(String::new());
-        let n = self.input.read_line(&mut buf)?;
-        if n == 0 {
-            return Ok(None);
+        match self.next_entry() {
+            Some((key, value)) => {
+                let result = seed
+                    .deserialize(NodeDeserializer::leaf(&key, self.options))
+                    .map_err(|err| {
+                        value
+                            .at(&KeyPath::new(self.key, Segment::Key(&key)))
+                            .locate(err)
+                    });
+                self.value = Some((key, value));
+                result.map(Some)
+            }
+            None => Ok(None),
         }
-        let (k, v) = self.parse_line(&buf)?;
-        self.current_key = Some(k.to_string());
-        self.current_value = Some(v.to_string());
-        let ret = seed.deserialize(&mut **self).map(Some);
-        self.current_key = None;
-        ret
     }
 
     fn next_value_seed<V: DeserializeSeed<'de>>(
         &mut self,
         seed: V,
     ) -> Result<V::Value, Self::Error> {
-        let ret = seed.deserialize(&mut **self);
-        self.current_value = None;
-        ret
+        let (key, value) = self.value.take().ok_or(ParseError::NoValue)?;
+        let key = KeyPath::new(self.key, Segment::Key(&key));
+        let value = value.at(&key);
+        seed.deserialize(value).map_err(|err| value.locate(err))
+    }
+
+    fn next_entry_seed<K: DeserializeSeed<'de>, V: DeserializeSeed<'de>>(
+        &mut self,
+        kseed: K,
+        vseed: V,
+    ) -> Result<Option<(K::Value, V::Value)>, Self::Error> {
+        let (mut key, node) = match self.next_entry() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let flatten = Cell::new(false);
+        let value = {
+            let key = KeyPath::new(self.key, Segment::Key(&key));
+            let node = node.at(&key);
+            vseed
+                .deserialize(NodeDeserializer {
+                    flatten: Some(&flatten),
+                    ..node
+                })
+                .map_err(|err| node.locate(err))?
+        };
+        if flatten.get() {
+            self.flat = true;
+            collect_leaves(node.children, 1, &mut self.leaves);
+            if node.value.is_none() {
+                if let Some((first, _)) = self.leaves.pop_front() {
+                    key = Cow::Owned(first);
+                }
+            }
+        }
+        let key = kseed
+            .deserialize(NodeDeserializer::leaf(&key, self.options))
+            .map_err(|err| {
+                node.at(&KeyPath::new(self.key, Segment::Key(&key)))
+                    .locate(err)
+            })?;
+        Ok(Some((key, value)))
     }
 }
 
-struct SeqDeserializer<'a, B: BufRead> {
-    deserializer: &'a mut Deserializer<B>,
-    index: usize,
-    values: Vec<String>
+/// Iterates the entries of a map held in a single value.
+struct InlineMapDeserializer<'a> {
+    entries: std::vec::IntoIter<(Cow<'a, str>, NodeDeserializer<'a>)>,
+    value: Option<(Cow<'a, str>, NodeDeserializer<'a>)>,
+    options: &'a PropertiesOptions,
 }
 
-impl<'a, 'de, B: BufRead> SeqDeserializer<'a, B> {
-    fn new(deserializer: &'a mut Deserializer<B>, values: Vec<String>) -> Self {
-        SeqDeserializer{
-            deserializer: deserializer,
-            index: 0,
-            values: values
-        }
+impl<'a> InlineMapDeserializer<'a> {
+    fn new(node: NodeDeserializer<'a>) -> Result<Self, Error> {
+        let escape = node.options.escape;
+        let entries = split_list(node.raw()?, &node.options.list_delimiter, escape)
+            .into_iter()
+            .map(|entry| {
+                let (key, value) =
+                    split_entry(entry, escape).ok_or_else(|| Error::InvalidValue {
+                        value: node.unescape(entry).into_owned(),
+                        expected: "key:value",
+                    })?;
+                let value = NodeDeserializer {
+                    value: Some(value),
+                    previous: &[],
+                    children: &[],
+                    flatten: None,
+                    group: None,
+                    ..node
+                };
+                Ok((node.unescape(key), value))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(InlineMapDeserializer {
+            entries: entries.into_iter(),
+            value: None,
+            options: node.options,
+        })
     }
 }
 
-impl<'de, 'a, B: BufRead> SeqAccess<'de> for SeqDeserializer<'a, B> {
+impl<'de, 'a> MapAccess<'de> for InlineMapDeserializer<'a> {
     type Error = Error;
 
-    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
-    where
-        T: DeserializeSeed<'de>,
-    {
-        if self.index >= self.values.len() {
-            Ok(None)
-        } else {
-            self.deserializer.current_value = Some(self.values[self.index].to_string());
-            self.index += 1;
-            seed.deserialize(&mut *self.deserializer).map(Some)
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                let result = seed
+                    .deserialize(NodeDeserializer::leaf(&key, self.options))
+                    .map_err(|err| {
+                        value
+                            .at(&KeyPath::new(value.key, Segment::Key(&key)))
+                            .locate(err)
+                    });
+                self.value = Some((key, value));
+                result.map(Some)
+            }
+            None => Ok(None),
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (key, value) = self.value.take().ok_or(ParseError::NoValue)?;
+        let key = KeyPath::new(value.key, Segment::Key(&key));
+        let value = value.at(&key);
+        seed.deserialize(value).map_err(|err| value.locate(err))
+    }
+}
+
+struct SeqDeserializer<'a> {
+    elements: std::iter::Enumerate<std::vec::IntoIter<NodeDeserializer<'a>>>,
+    /// The elements split on the secondary delimiter, read instead of
+    /// `elements` if the first element turns out to be a sequence.
+    groups: Option<Vec<NodeDeserializer<'a>>>,
+    /// The number of elements of a tuple.
+    len: Option<usize>,
+    key: Option<&'a KeyPath<'a>>,
+}
+
+impl<'a> SeqDeserializer<'a> {
+    fn new(node: NodeDeserializer<'a>, len: Option<usize>) -> Result<Self, Error> {
+        let seq = SeqDeserializer {
+            elements: node.elements()?.into_iter().enumerate(),
+            groups: node.groups()?,
+            len,
+            key: node.key,
+        };
+        if seq.groups.is_none() {
+            seq.check_len(0)?;
+        }
+        Ok(seq)
+    }
+
+    /// Checks a tuple has as many elements as expected, once it's known how
+    /// its value is split.
+    fn check_len(&self, read: usize) -> Result<(), Error> {
+        match self.len {
+            Some(len) if read + self.elements.len() != len => Err(Error::Custom(format!(
+                "Expected {} elements but found {}",
+                len,
+                read + self.elements.len()
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<'de, 'a> SeqAccess<'de> for SeqDeserializer<'a> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let (index, element) = match self.elements.next() {
+            Some(element) => element,
+            None => return Ok(None),
+        };
+        let key = KeyPath::new(self.key, Segment::Index(index));
+        let element = element.at(&key);
+        let groups = match self.groups.take() {
+            Some(groups) => groups,
+            None => {
+                return seed
+                    .deserialize(element)
+                    .map(Some)
+                    .map_err(|err| element.locate(err));
+            }
+        };
+        let nested = Cell::new(false);
+        let value = {
+            let element = NodeDeserializer {
+                group: groups
+                    .first()
+                    .and_then(|group| group.value)
+                    .map(|group| (group, &nested)),
+                ..element
+            };
+            seed.deserialize(element)
+                .map_err(|err| element.locate(err))?
+        };
+        if nested.get() {
+            self.elements = groups.into_iter().enumerate();
+            self.elements.next();
+        }
+        self.check_len(1)?;
+        Ok(Some(value))
+    }
+}
+
+/// Deserializes an enum variant and the node holding its data, if any.
+struct EnumDeserializer<'a> {
+    variant: Cow<'a, str>,
+    data: Option<NodeDeserializer<'a>>,
+}
+
+impl<'a> EnumDeserializer<'a> {
+    fn new(variant: Cow<'a, str>, data: Option<NodeDeserializer<'a>>) -> Self {
+        EnumDeserializer { variant, data }
+    }
+
+    fn data(&self) -> Result<NodeDeserializer<'a>, Error> {
+        self.data.ok_or_else(|| {
+            Error::Custom(format!(
+                "Expected data for variant {} in nested keys",
+                self.variant
+            ))
+        })
+    }
+}
+
+impl<'de, 'a> EnumAccess<'de> for EnumDeserializer<'a> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let variant: de::value::StrDeserializer<Error> = self.variant.as_ref().into_deserializer();
+        let variant = seed.deserialize(variant)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a> VariantAccess<'de> for EnumDeserializer<'a> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        match self.data {
+            None => Ok(()),
+            Some(_) => Err(Error::Custom(format!(
+                "Unexpected nested keys for unit variant {}",
+                self.variant
+            ))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        let data = self.data()?;
+        let variant = self.variant;
+        let key = KeyPath::new(data.key, Segment::Key(&variant));
+        let data = data.at(&key);
+        seed.deserialize(data).map_err(|err| data.locate(err))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let data = self.data()?;
+        let variant = self.variant;
+        let key = KeyPath::new(data.key, Segment::Key(&variant));
+        let data = data.at(&key);
+        de::Deserializer::deserialize_tuple(data, len, visitor).map_err(|err| data.locate(err))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let data = self.data()?;
+        let variant = self.variant;
+        let key = KeyPath::new(data.key, Segment::Key(&variant));
+        let data = data.at(&key);
+        de::Deserializer::deserialize_map(data, visitor).map_err(|err| data.locate(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
     fn test() {
         #[derive(Deserialize, PartialEq, Debug)]
         struct Test {
@@ -370,4 +1551,1206 @@ mod tests {
         let t: Test = from_str(r#"int=1"#).unwrap();
         assert_eq!(t.int, 1);
     }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            host: String,
+            port: u16,
+            user: String,
+        }
+        let input = r#"#Database connection settings
+#Tue Mar 03 14:12:09 UTC 2020
+
+# Primary host, override per environment
+host=db.internal
+
+! Legacy style comment
+   # Indented comment
+	! Tab indented comment
+
+port=5432
+    
+user=admin
+# trailing comment without newline"#;
+        let c: Config = from_str(input).unwrap();
+        assert_eq!(
+            c,
+            Config {
+                host: "db.internal".to_string(),
+                port: 5432,
+                user: "admin".to_string(),
+            }
+        );
+
+        let c: Config = from_str(&input.replace('\n', "\r\n")).unwrap();
+        assert_eq!(c.port, 5432);
+    }
+
+    #[test]
+    fn carriage_returns() {
+        use std::collections::BTreeMap;
+        let m: BTreeMap<String, String> = from_str("a=1\rb=2\r\n\r# c\rd=3,\\\r  4\re=5").unwrap();
+        let entries: Vec<_> = m.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(entries, [("a", "1"), ("b", "2"), ("d", "3,4"), ("e", "5")]);
+        let err = from_str::<BTreeMap<String, u8>>("a=1\rb=x\r").unwrap_err();
+        assert_eq!(err.line(), Some(2));
+    }
+
+    #[test]
+    fn only_comments() {
+        use std::collections::HashMap;
+        let m: HashMap<String, String> = from_str("# nothing here\n\n! at all\n").unwrap();
+        assert!(m.is_empty());
+    }
+
+    #[test]
+    fn line_continuation() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            classpath: String,
+            url: String,
+            after: u32,
+        }
+        let input = "classpath=lib/a.jar:\\\n    lib/b.jar:\\\n\tlib/c.jar\n\
+                     url = jdbc:postgresql://db.internal:5432/app\\\n  ?ssl=true\n\
+                     after=1\n";
+        let c: Config = from_str(input).unwrap();
+        assert_eq!(
+            c,
+            Config {
+                classpath: "lib/a.jar:lib/b.jar:lib/c.jar".to_string(),
+                url: "jdbc:postgresql://db.internal:5432/app?ssl=true".to_string(),
+                after: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn line_continuation_backslash_parity() {
+        use std::collections::HashMap;
+        // Two trailing backslashes are an escaped backslash, not a continuation.
+        let m: HashMap<String, String> = from_str("even=a\\\\\nnext=1\n").unwrap();
+        assert_eq!(m.len(), 2);
+        assert_eq!(m["next"], "1");
+
+        // Three trailing backslashes end in a continuation.
+        let m: HashMap<String, String> = from_str("odd=a\\\\\\\n  b\nnext=1\n").unwrap();
+        assert_eq!(m.len(), 2);
+        assert_eq!(m["next"], "1");
+
+        // A continuation at end of input drops the backslash.
+        let m: HashMap<String, String> = from_str("last=a\\").unwrap();
+        assert_eq!(m["last"], "a");
+
+        // Continued lines are never comments, and an empty line ends the value.
+        let m: HashMap<String, String> = from_str("k=a\\\n  #b\\\n\n#c=d\n").unwrap();
+        assert_eq!(m.len(), 1);
+        assert_eq!(m["k"], "a#b");
+    }
+
+    #[test]
+    fn escapes() {
+        use std::collections::HashMap;
+        let input = r#"a\=b=c
+tabs=one\ttwo
+lines=first\nsecond\r\nthird
+feed=\f
+path=C:\\Program Files\\App
+other=\q\#\!\:
+"#;
+        let m: HashMap<String, String> = from_str(input).unwrap();
+        assert_eq!(m["a=b"], "c");
+        assert_eq!(m["tabs"], "one\ttwo");
+        assert_eq!(m["lines"], "first\nsecond\r\nthird");
+        assert_eq!(m["feed"], "\x0c");
+        assert_eq!(m["path"], "C:\\Program Files\\App");
+        assert_eq!(m["other"], "q#!:");
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        use std::collections::HashMap;
+        let input = r#"caf\u00e9=\u00E9t\u00e9
+greeting=\u3053\u3093\u306b\u3061\u306f
+emoji=\uD83D\uDE00
+"#;
+        let m: HashMap<String, String> = from_str(input).unwrap();
+        assert_eq!(m["café"], "été");
+        assert_eq!(m["greeting"], "こんにちは");
+        assert_eq!(m["emoji"], "😀");
+
+        for input in &[
+            "k=\\u12",
+            "k=\\u12G4",
+            "k=\\uD83D",
+            "k=\\uD83Dx",
+            "k=\\uD83D\\u0041",
+            "k=\\uDE00",
+        ] {
+            let err = from_str::<HashMap<String, String>>(input).unwrap_err();
+            assert!(
+                matches!(
+                    &err,
+                    Error::Located { error, .. }
+                        if matches!(**error, Error::Parse(ParseError::InvalidUnicodeEscape))
+                ),
+                "{}: {:?}",
+                input,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn separators() {
+        use std::collections::HashMap;
+        let input = r#"equals=1
+colon:2
+space 3
+tab	4
+spaced = 5
+spaced_colon : 6
+spaced_both   =   7
+double==8
+colon_equals:=9
+space_equals = =10
+key_only
+key_only_spaced   
+empty=
+escaped\:key\ name\=x = 11
+"#;
+        let m: HashMap<String, String> = from_str(input).unwrap();
+        assert_eq!(m["equals"], "1");
+        assert_eq!(m["colon"], "2");
+        assert_eq!(m["space"], "3");
+        assert_eq!(m["tab"], "4");
+        assert_eq!(m["spaced"], "5");
+        assert_eq!(m["spaced_colon"], "6");
+        assert_eq!(m["spaced_both"], "7");
+        assert_eq!(m["double"], "=8");
+        assert_eq!(m["colon_equals"], "=9");
+        assert_eq!(m["space_equals"], "=10");
+        assert_eq!(m["key_only"], "");
+        assert_eq!(m["key_only_spaced"], "");
+        assert_eq!(m["empty"], "");
+        assert_eq!(m["escaped:key name=x"], "11");
+        assert_eq!(m.len(), 14);
+
+        for input in &["=value", ": value", "  = value"] {
+            let m: HashMap<String, String> = from_str(input).unwrap();
+            assert_eq!(m.len(), 1);
+            assert_eq!(m[""], "value");
+        }
+    }
+
+    #[test]
+    fn whitespace() {
+        use std::collections::HashMap;
+        let input = "trailing=value  \n\
+                     tabbed = value\t\n\
+                     leading=\\  value\n\
+                     escaped_trailing=value\\ \n\
+                     continued=one \\\n    two \n";
+        let m: HashMap<String, String> = from_str(input).unwrap();
+        assert_eq!(m["trailing"], "value  ");
+        assert_eq!(m["tabbed"], "value\t");
+        assert_eq!(m["leading"], "  value");
+        assert_eq!(m["escaped_trailing"], "value ");
+        assert_eq!(m["continued"], "one two ");
+
+        let options = PropertiesOptions::new().trim_values(true);
+        let m: HashMap<String, String> = from_str_with(input, &options).unwrap();
+        assert_eq!(m["trailing"], "value");
+        assert_eq!(m["tabbed"], "value");
+        assert_eq!(m["leading"], "  value");
+        assert_eq!(m["escaped_trailing"], "value ");
+        assert_eq!(m["continued"], "one two");
+    }
+
+    #[test]
+    fn options() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            name: String,
+            hosts: Vec<String>,
+        }
+        let options = PropertiesOptions::new()
+            .escape('^')
+            .separator('>')
+            .list_delimiter(";");
+        let c: Config = from_str_with("name>a^>b^tc\\d\nhosts>a;b,c\n", &options).unwrap();
+        assert_eq!(
+            c,
+            Config {
+                name: "a>b\tc\\d".to_string(),
+                hosts: vec!["a".to_string(), "b,c".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn nested_structs() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            name: String,
+            db: Db,
+            cache: Option<Cache>,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Db {
+            host: String,
+            port: u16,
+            pool: Pool,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Pool {
+            min: u32,
+            max: u32,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Cache {
+            ttl: u64,
+        }
+        let input = r#"
+# Service
+name=orders
+db.pool.min=1
+db.host=db.internal
+db.port=5432
+db.pool.max=10
+db.unknown.setting=ignored
+"#;
+        let c: Config = from_str(input).unwrap();
+        assert_eq!(
+            c,
+            Config {
+                name: "orders".to_string(),
+                db: Db {
+                    host: "db.internal".to_string(),
+                    port: 5432,
+                    pool: Pool { min: 1, max: 10 },
+                },
+                cache: None,
+            }
+        );
+
+        let c: Config = from_str(&format!("{}cache.ttl=60\n", input)).unwrap();
+        assert_eq!(c.cache, Some(Cache { ttl: 60 }));
+
+        assert!(from_str::<Config>("name=orders\ndb.host=x\n").is_err());
+    }
+
+    #[test]
+    fn dotted_keys_in_maps() {
+        use std::collections::HashMap;
+        let input = "app.name=orders\napp.version=2\nport=80\nlog.level.root=info\n";
+        let m: HashMap<String, String> = from_str(input).unwrap();
+        assert_eq!(m.len(), 4);
+        assert_eq!(m["app.name"], "orders");
+        assert_eq!(m["app.version"], "2");
+        assert_eq!(m["port"], "80");
+        assert_eq!(m["log.level.root"], "info");
+
+        let m: HashMap<String, String> = from_str("a=1\na.b=2\nescaped\\.dot=3\n").unwrap();
+        assert_eq!(m["a"], "1");
+        assert_eq!(m["a.b"], "2");
+        assert_eq!(m["escaped.dot"], "3");
+    }
+
+    #[test]
+    fn indexed_sequences() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            servers: Vec<Server>,
+            hosts: Vec<String>,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Server {
+            host: String,
+            port: u16,
+        }
+        let expected = Config {
+            servers: vec![
+                Server {
+                    host: "a".to_string(),
+                    port: 80,
+                },
+                Server {
+                    host: "b".to_string(),
+                    port: 81,
+                },
+            ],
+            hosts: vec!["x".to_string(), "y".to_string()],
+        };
+
+        let input = r#"servers[0].host=a
+servers[0].port=80
+servers[1].host=b
+servers[1].port=81
+hosts[0]=x
+hosts[1]=y
+"#;
+        assert_eq!(from_str::<Config>(input).unwrap(), expected);
+
+        let input = r#"servers.0.host=a
+servers.1.host=b
+servers.0.port=80
+servers.1.port=81
+hosts=x,y
+"#;
+        assert_eq!(from_str::<Config>(input).unwrap(), expected);
+
+        let sparse = "servers[0].host=a\nservers[0].port=80\nservers[2].host=b\nservers[2].port=81\nhosts=x\n";
+        assert!(from_str::<Config>(sparse).is_err());
+
+        let out_of_order = "servers[1].host=a\nservers[1].port=80\nservers[0].host=b\nservers[0].port=81\nhosts=x\n";
+        assert!(from_str::<Config>(out_of_order).is_err());
+
+        let signed = "servers[0].host=a\nservers[0].port=80\nhosts[0]=x\nhosts.+1=y\n";
+        assert_eq!(
+            from_str::<Config>(signed).unwrap_err().to_string(),
+            "Parse error: Missing value at line 3, column 1 (key hosts)"
+        );
+    }
+
+    #[test]
+    fn split_key() {
+        let deserializer = Deserializer::new("".as_bytes());
+        let split = |key| {
+            let key = deserializer.split_key(key).unwrap();
+            key.segments().map(str::to_string).collect::<Vec<_>>()
+        };
+        assert_eq!(split("a.b.c"), ["a", "b", "c"]);
+        assert_eq!(split("a[0].b"), ["a", "0", "b"]);
+        assert_eq!(split("a[0][1]"), ["a", "0", "1"]);
+        assert_eq!(split("a[0]b"), ["a", "0", "b"]);
+        assert_eq!(split("a[0"), ["a[0"]);
+        assert_eq!(split("a\\[0]"), ["a[0]"]);
+        assert_eq!(split("a\\.b"), ["a.b"]);
+        assert_eq!(split("x[y]"), ["x[y]"]);
+        assert_eq!(split("a[]"), ["a[]"]);
+        assert_eq!(split("a[0x1].b"), ["a[0x1]", "b"]);
+    }
+
+    #[test]
+    fn flat_map_keys_as_written() {
+        use std::collections::BTreeMap;
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            labels: BTreeMap<String, String>,
+        }
+        let m: BTreeMap<String, String> = from_str("list[0]=a\nx[y]=b\na.b=c\nd\\.e=f\n").unwrap();
+        let keys: Vec<_> = m.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["a.b", "d.e", "list[0]", "x[y]"]);
+        let c: Config = from_str("labels.env[0]=prod\nlabels.team.name=core\n").unwrap();
+        let keys: Vec<_> = c.labels.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["env[0]", "team.name"]);
+    }
+
+    #[test]
+    fn maps_of_structs() {
+        use std::collections::{BTreeMap, HashMap};
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            datasource: HashMap<String, DataSource>,
+            ports: BTreeMap<u16, String>,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct DataSource {
+            url: String,
+            pool: Option<u32>,
+        }
+        let input = r#"datasource.primary.url=jdbc:postgresql://primary/app
+datasource.primary.pool=10
+datasource.replica.url=jdbc:postgresql://replica/app
+datasource.reporting\.eu.url=jdbc:postgresql://reporting/app
+ports.80=http
+ports.443=https
+"#;
+        let c: Config = from_str(input).unwrap();
+        assert_eq!(c.datasource.len(), 3);
+        assert_eq!(
+            c.datasource["primary"],
+            DataSource {
+                url: "jdbc:postgresql://primary/app".to_string(),
+                pool: Some(10),
+            }
+        );
+        assert_eq!(
+            c.datasource["replica"],
+            DataSource {
+                url: "jdbc:postgresql://replica/app".to_string(),
+                pool: None,
+            }
+        );
+        assert_eq!(
+            c.datasource["reporting.eu"].url,
+            "jdbc:postgresql://reporting/app"
+        );
+        assert_eq!(c.ports[&80], "http");
+        assert_eq!(c.ports[&443], "https");
+
+        let nested: HashMap<String, HashMap<String, DataSource>> =
+            from_str("eu.primary.url=a\nus.primary.url=b\nus.replica.url=c\n").unwrap();
+        assert_eq!(nested["eu"]["primary"].url, "a");
+        assert_eq!(nested["us"]["replica"].url, "c");
+
+        assert!(from_str::<HashMap<String, DataSource>>("primary=a\n").is_err());
+    }
+
+    #[test]
+    fn unit_enums() {
+        use std::collections::HashMap;
+        #[derive(Deserialize, PartialEq, Debug)]
+        enum Level {
+            Debug,
+            Info,
+            #[serde(rename = "WARN")]
+            Warn,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            log_level: Level,
+            fallback: Option<Level>,
+            loggers: HashMap<String, Level>,
+        }
+        let input = "log_level=Debug\nfallback=\nloggers.com.example=WARN\nloggers.root=Info\n";
+        let c: Config = from_str(input).unwrap();
+        assert_eq!(c.log_level, Level::Debug);
+        assert_eq!(c.fallback, None);
+        assert_eq!(c.loggers["com.example"], Level::Warn);
+        assert_eq!(c.loggers["root"], Level::Info);
+
+        let err = from_str::<Config>("log_level=debug\nloggers.root=Info\n").unwrap_err();
+        assert!(
+            err.to_string().starts_with("unknown variant `debug`"),
+            "{:?}",
+            err
+        );
+
+        let options = PropertiesOptions::new().case_insensitive_enums(true);
+        let c: Config = from_str_with(
+            "log_level=debug\nloggers.root=INFO\nloggers.a=warn\n",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(c.log_level, Level::Debug);
+        assert_eq!(c.loggers["root"], Level::Info);
+        assert_eq!(c.loggers["a"], Level::Warn);
+        assert!(from_str_with::<Config>("log_level=trace\n", &options).is_err());
+    }
+
+    #[test]
+    fn data_enums() {
+        use std::collections::HashMap;
+        #[derive(Deserialize, PartialEq, Debug)]
+        enum Transport {
+            Local,
+            Udp(u16),
+            Pair(String, u16),
+            Tcp { host: String, port: u16 },
+            Tls(Tls),
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Tls {
+            cert: String,
+        }
+        let t: HashMap<String, Transport> = from_str(
+            r#"local=Local
+udp.Udp=53
+pair.Pair=localhost,80
+tcp.Tcp.host=localhost
+tcp.Tcp.port=8080
+tls.Tls.cert=/etc/cert.pem
+"#,
+        )
+        .unwrap();
+        assert_eq!(t["local"], Transport::Local);
+        assert_eq!(t["udp"], Transport::Udp(53));
+        assert_eq!(t["pair"], Transport::Pair("localhost".to_string(), 80));
+        assert_eq!(
+            t["tcp"],
+            Transport::Tcp {
+                host: "localhost".to_string(),
+                port: 8080,
+            }
+        );
+        assert_eq!(
+            t["tls"],
+            Transport::Tls(Tls {
+                cert: "/etc/cert.pem".to_string(),
+            })
+        );
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            transport: Transport,
+        }
+        assert!(from_str::<Config>("transport=Udp\n").is_err());
+        assert!(from_str::<Config>("transport.Local=1\n").is_err());
+        assert!(from_str::<Config>("transport.Udp=1\ntransport.Local=\n").is_err());
+        assert!(from_str::<Config>("transport.Http.port=1\n").is_err());
+    }
+
+    #[test]
+    fn tagged_enums() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        #[serde(tag = "type", rename_all = "lowercase")]
+        enum Storage {
+            Memory,
+            S3 { bucket: String, region: String },
+            Disk(Disk),
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Disk {
+            path: String,
+            quota: u64,
+            mounts: Vec<Mount>,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Mount {
+            at: String,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        #[serde(tag = "kind", content = "spec")]
+        enum Auth {
+            None,
+            Token(String),
+            Basic { user: String, password: String },
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            storage: Storage,
+            backups: Vec<Storage>,
+            auth: Auth,
+            fallback: Auth,
+        }
+        let c: Config = from_str(
+            r#"storage.type=s3
+storage.bucket=plugins
+storage.region=eu-west-1
+backups[0].type=memory
+backups[1].path=/var/backups
+backups[1].type=disk
+backups[1].quota=1024
+backups[1].mounts[0].at=/mnt/a
+backups[1].mounts[1].at=/mnt/b
+auth.kind=Basic
+auth.spec.user=admin
+auth.spec.password=secret
+fallback.spec=abc
+fallback.kind=Token
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            c,
+            Config {
+                storage: Storage::S3 {
+                    bucket: "plugins".to_string(),
+                    region: "eu-west-1".to_string(),
+                },
+                backups: vec![
+                    Storage::Memory,
+                    Storage::Disk(Disk {
+                        path: "/var/backups".to_string(),
+                        quota: 1024,
+                        mounts: vec![
+                            Mount {
+                                at: "/mnt/a".to_string(),
+                            },
+                            Mount {
+                                at: "/mnt/b".to_string(),
+                            },
+                        ],
+                    }),
+                ],
+                auth: Auth::Basic {
+                    user: "admin".to_string(),
+                    password: "secret".to_string(),
+                },
+                fallback: Auth::Token("abc".to_string()),
+            }
+        );
+
+        assert!(from_str::<Config>("storage.type=tape\n").is_err());
+    }
+
+    #[test]
+    fn flatten_and_untagged() {
+        use std::collections::HashMap;
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            name: String,
+            #[serde(flatten)]
+            db: Db,
+            #[serde(flatten)]
+            extra: HashMap<String, Extra>,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Db {
+            host: String,
+            port: u16,
+            ssl: bool,
+            ratio: f64,
+            offset: i32,
+            password: Option<String>,
+            pool: Pool,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Pool {
+            max: u32,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        #[serde(untagged)]
+        enum Extra {
+            Flag(bool),
+            Count(u64),
+            Float(f64),
+            Text(String),
+            Group(HashMap<String, String>),
+        }
+        let c: Config = from_str(
+            r#"name=orders
+host=db.internal
+port=5432
+ssl=true
+ratio=0.5
+offset=-3
+password=
+pool.max=10
+retries=3
+debug=false
+version=1.2.3
+threshold=2.5e3
+mode=nan
+labels.team=core
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            c.db,
+            Db {
+                host: "db.internal".to_string(),
+                port: 5432,
+                ssl: true,
+                ratio: 0.5,
+                offset: -3,
+                password: None,
+                pool: Pool { max: 10 },
+            }
+        );
+        assert_eq!(c.extra.len(), 6);
+        assert_eq!(c.extra["retries"], Extra::Count(3));
+        assert_eq!(c.extra["debug"], Extra::Flag(false));
+        assert_eq!(c.extra["version"], Extra::Text("1.2.3".to_string()));
+        assert_eq!(c.extra["threshold"], Extra::Float(2500.0));
+        assert_eq!(c.extra["mode"], Extra::Text("nan".to_string()));
+        let mut labels = HashMap::new();
+        labels.insert("team".to_string(), "core".to_string());
+        assert_eq!(c.extra["labels"], Extra::Group(labels));
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        #[serde(untagged)]
+        enum Source {
+            Url { url: String },
+            Path { path: String, readonly: bool },
+            Port(u16),
+            Name(String),
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Sources {
+            a: Source,
+            b: Source,
+            c: Source,
+            d: Source,
+        }
+        let s: Sources =
+            from_str("a.url=http://x\nb.path=/tmp\nb.readonly=true\nc=8080\nd=http\n").unwrap();
+        assert_eq!(
+            s,
+            Sources {
+                a: Source::Url {
+                    url: "http://x".to_string(),
+                },
+                b: Source::Path {
+                    path: "/tmp".to_string(),
+                    readonly: true,
+                },
+                c: Source::Port(8080),
+                d: Source::Name("http".to_string()),
+            }
+        );
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Labels {
+            name: String,
+            port: u16,
+            #[serde(flatten)]
+            rest: HashMap<String, String>,
+        }
+        let options = PropertiesOptions::new().infer_types(false);
+        let l: Labels = from_str_with(
+            "name=orders\nport=80\nretries=3\ndebug=\nssl=true\nratio=0.5\n",
+            &options,
+        )
+        .unwrap();
+        assert_eq!((l.name.as_str(), l.port), ("orders", 80));
+        assert_eq!(l.rest.len(), 4);
+        assert_eq!(l.rest["retries"], "3");
+        assert_eq!(l.rest["debug"], "");
+        assert_eq!(l.rest["ssl"], "true");
+        assert_eq!(l.rest["ratio"], "0.5");
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Service {
+            name: String,
+            #[serde(flatten)]
+            extra: HashMap<String, Extra>,
+        }
+        let s: Service = from_str("name=web\nports.80=http\nports.443=https\n").unwrap();
+        let mut ports = HashMap::new();
+        ports.insert("80".to_string(), "http".to_string());
+        ports.insert("443".to_string(), "https".to_string());
+        assert_eq!(s.extra["ports"], Extra::Group(ports));
+    }
+
+    #[test]
+    fn tuples() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Point(i32, i32);
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            origin: (i32, i32),
+            point: Point,
+            range: (u16, u16),
+            rgb: [u8; 3],
+            servers: (Server, Server),
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Server {
+            host: String,
+        }
+        let c: Config = from_str(
+            r#"origin=0,-1
+point=3,4
+range=8000,8080
+rgb=255,128,0
+servers[0].host=a
+servers[1].host=b
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            c,
+            Config {
+                origin: (0, -1),
+                point: Point(3, 4),
+                range: (8000, 8080),
+                rgb: [255, 128, 0],
+                servers: (
+                    Server {
+                        host: "a".to_string(),
+                    },
+                    Server {
+                        host: "b".to_string(),
+                    },
+                ),
+            }
+        );
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Single {
+            point: Point,
+        }
+        assert_eq!(
+            from_str::<Single>("point=1,2,3\n").unwrap_err().to_string(),
+            "Expected 2 elements but found 3 at line 1, column 7 (key point)"
+        );
+        assert!(from_str::<Single>("point=1\n").is_err());
+        assert!(from_str::<Single>("point=1,x\n").is_err());
+    }
+
+    #[test]
+    fn bytes() {
+        use crate::BinaryEncoding;
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Keys {
+            #[serde(with = "serde_bytes")]
+            secret: Vec<u8>,
+            #[serde(with = "serde_bytes")]
+            empty: Vec<u8>,
+            plain: Vec<u8>,
+        }
+        let expected = Keys {
+            secret: vec![0xfb, 0xff, 0x00, 0x10],
+            empty: Vec::new(),
+            plain: vec![1, 2],
+        };
+        let k: Keys = from_str("secret=+/8AEA==\nempty=\nplain=1,2\n").unwrap();
+        assert_eq!(k, expected);
+        let k: Keys = from_str("secret=+/8AEA\nempty=\nplain=1,2\n").unwrap();
+        assert_eq!(k, expected);
+
+        let options = PropertiesOptions::new().binary_encoding(BinaryEncoding::Base64UrlSafe);
+        let k: Keys = from_str_with("secret=-_8AEA==\nempty=\nplain=1,2\n", &options).unwrap();
+        assert_eq!(k, expected);
+
+        let options = PropertiesOptions::new().binary_encoding(BinaryEncoding::Hex);
+        let k: Keys = from_str_with("secret=FBff0010\nempty=\nplain=1,2\n", &options).unwrap();
+        assert_eq!(k, expected);
+        assert_eq!(
+            from_str_with::<Keys>("secret=fbf\nempty=\nplain=\n", &options)
+                .unwrap_err()
+                .to_string(),
+            "Invalid hex value: Odd number of digits at line 1, column 8 (key secret)"
+        );
+        assert_eq!(
+            from_str_with::<Keys>("secret=+f\nempty=\nplain=\n", &options)
+                .unwrap_err()
+                .to_string(),
+            "Invalid hex value: Invalid digits at offset 0 at line 1, column 8 (key secret)"
+        );
+
+        #[derive(Deserialize, Debug)]
+        struct Nested {
+            #[allow(dead_code)]
+            tls: Vec<Tls>,
+        }
+        #[derive(Deserialize, Debug)]
+        struct Tls {
+            #[allow(dead_code)]
+            #[serde(with = "serde_bytes")]
+            key: Vec<u8>,
+        }
+        let err = from_str::<Nested>("tls[0].key=AAAA\ntls[1].key=A*AA\n").unwrap_err();
+        assert_eq!(err.key(), Some("tls[1].key"));
+        assert_eq!(err.line(), Some(2));
+        assert!(
+            err.to_string().starts_with("Invalid base64 value: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn error_positions() {
+        use std::collections::HashMap;
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Config {
+            name: String,
+            db: Db,
+            servers: Vec<Server>,
+        }
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Db {
+            host: String,
+            port: u16,
+        }
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Server {
+            weight: u8,
+        }
+        let position = |input: &str| {
+            let err = from_str::<Config>(input).unwrap_err();
+            (err.line(), err.column(), err.key().map(str::to_string))
+        };
+        assert_eq!(
+            position("name=a\n# comment\n\ndb.host=h\ndb.port =  80x\nservers[0].weight=1\n"),
+            (Some(5), Some(12), Some("db.port".to_string()))
+        );
+        assert_eq!(
+            position("name=a\ndb.host=h\ndb.port=1\nservers[0].weight=1\nservers[1].weight=300\n"),
+            (Some(5), Some(19), Some("servers[1].weight".to_string()))
+        );
+        assert_eq!(
+            position("name=a\ndb.host=h\nservers[0].weight=1\n"),
+            (Some(2), Some(1), Some("db".to_string()))
+        );
+        assert_eq!(
+            position("name=a\n  db.host=a,\\\n      b\\u00\ndb.port=1\nservers[0].weight=1\n"),
+            (Some(3), Some(8), Some("db.host".to_string()))
+        );
+        assert_eq!(
+            position("name=a\n\tdb.host=h\n  x=\\uZZZZ\n"),
+            (Some(3), Some(5), Some("x".to_string()))
+        );
+
+        let err = from_str::<HashMap<String, String>>("a=1\nb=\\uZZZZ\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Parse error: Invalid unicode escape, expected \\uXXXX at line 2, column 3 (key b)"
+        );
+    }
+
+    #[test]
+    fn conversion_errors() {
+        use serde::de::Error as _;
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Config {
+            port: u16,
+            timeout: u64,
+            debug: bool,
+            ratio: f32,
+            initial: char,
+            servers: Vec<Server>,
+            ports: Vec<u16>,
+        }
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Server {
+            #[serde(deserialize_with = "url")]
+            url: String,
+        }
+        fn url<'de, D: de::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+            let url = String::deserialize(deserializer)?;
+            if url.contains("://") {
+                Ok(url)
+            } else {
+                Err(D::Error::custom(format!("Not a URL: {}", url)))
+            }
+        }
+        let valid = [
+            "port=80",
+            "timeout=30",
+            "debug=true",
+            "ratio=0.5",
+            "initial=x",
+            "servers[0].url=http://a",
+            "ports=1,2",
+        ];
+        let error = |index: usize, line: &str| {
+            let mut input = valid.to_vec();
+            input[index] = line;
+            from_str::<Config>(&input.join("\n")).unwrap_err()
+        };
+        let invalid = |index, line, key: &str, value: &str, expected: &str| {
+            let err = error(index, line);
+            assert_eq!(err.key(), Some(key), "{}", err);
+            assert_eq!(err.line(), Some(index + 1), "{}", err);
+            match err {
+                Error::Located { error, .. } => match *error {
+                    Error::InvalidValue {
+                        value: v,
+                        expected: e,
+                    } => assert_eq!((v.as_str(), e), (value, expected)),
+                    other => panic!("unexpected {:?}", other),
+                },
+                other => panic!("unexpected {:?}", other),
+            }
+        };
+        invalid(0, "port=80x", "port", "80x", "u16");
+        invalid(0, "port=70000", "port", "70000", "u16");
+        invalid(1, "timeout=abc", "timeout", "abc", "u64");
+        invalid(2, "debug=yes", "debug", "yes", "bool");
+        invalid(3, "ratio=half", "ratio", "half", "f32");
+        invalid(4, "initial=xy", "initial", "xy", "char");
+        invalid(6, "ports=1,x", "ports[1]", "x", "u16");
+        assert_eq!(
+            error(0, "port=80x").to_string(),
+            "Invalid value `80x`, expected u16 at line 1, column 6 (key port)"
+        );
+
+        let err = error(5, "servers[0].url=a");
+        assert_eq!(err.key(), Some("servers[0].url"));
+        assert_eq!(
+            err.to_string(),
+            "Not a URL: a at line 6, column 16 (key servers[0].url)"
+        );
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        use crate::DuplicateKeys;
+        use std::collections::HashMap;
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            name: String,
+            hosts: Vec<String>,
+            db: Db,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Db {
+            port: u16,
+        }
+        let input = "name=a\nhosts=x\ndb.port=1\nname=b\nhosts=y,z\ndb.port=2\n";
+        let read = |policy| {
+            let options = PropertiesOptions::new().duplicate_keys(policy);
+            from_str_with::<Config>(input, &options)
+        };
+        let config = |name: &str, hosts: &[&str], port| Config {
+            name: name.to_string(),
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+            db: Db { port },
+        };
+        assert_eq!(
+            from_str::<Config>(input).unwrap(),
+            config("b", &["y", "z"], 2)
+        );
+        assert_eq!(
+            read(DuplicateKeys::LastWins).unwrap(),
+            config("b", &["y", "z"], 2)
+        );
+        assert_eq!(
+            read(DuplicateKeys::FirstWins).unwrap(),
+            config("a", &["x"], 1)
+        );
+        assert_eq!(
+            read(DuplicateKeys::Collect).unwrap(),
+            config("b", &["x", "y", "z"], 2)
+        );
+        let err = read(DuplicateKeys::Error).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Parse error: Duplicate key, first set on line 1 at line 4, column 1 (key name)"
+        );
+
+        let options = PropertiesOptions::new().duplicate_keys(DuplicateKeys::Error);
+        let err = from_str_with::<HashMap<String, String>>("a.b=1\na.c=2\n a.b = 3\n", &options)
+            .unwrap_err();
+        assert_eq!(
+            (err.line(), err.column(), err.key()),
+            (Some(3), Some(2), Some("a.b"))
+        );
+        let options = PropertiesOptions::new().duplicate_keys(DuplicateKeys::FirstWins);
+        let m: HashMap<String, String> = from_str_with("a=1\nb=2\na=3\n", &options).unwrap();
+        assert_eq!((m["a"].as_str(), m["b"].as_str()), ("1", "2"));
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Lists {
+            #[serde(flatten)]
+            lists: HashMap<String, Vec<u8>>,
+        }
+        let options = PropertiesOptions::new().duplicate_keys(DuplicateKeys::Collect);
+        let l: Lists = from_str_with("a=1\nb=2\nb=4,5\na=3\n", &options).unwrap();
+        assert_eq!(
+            (&l.lists["a"][..], &l.lists["b"][..]),
+            (&[1, 3][..], &[2, 4, 5][..])
+        );
+    }
+
+    #[test]
+    fn repeat_keys() {
+        use crate::DuplicateKeys;
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            allow: Vec<String>,
+            origin: (i32, i32),
+            name: String,
+        }
+        let input = "allow=a\nname=x\norigin=0\nallow=b,c\norigin=-1\nname=y\n";
+        let expected = Config {
+            allow: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            origin: (0, -1),
+            name: "y".to_string(),
+        };
+        let options = PropertiesOptions::new().repeat_keys(true);
+        assert_eq!(from_str_with::<Config>(input, &options).unwrap(), expected);
+        let options = options.duplicate_keys(DuplicateKeys::Error);
+        assert_eq!(from_str_with::<Config>(input, &options).unwrap(), expected);
+    }
+
+    #[test]
+    fn lists() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            tags: Vec<String>,
+            #[serde(default)]
+            ports: Vec<u16>,
+        }
+        let tags = |input: &str| from_str::<Config>(input).unwrap().tags;
+        assert_eq!(tags("tags=a\\,b,c"), ["a,b", "c"]);
+        assert_eq!(tags("tags= a ,  b\t"), ["a", "b"]);
+        assert_eq!(tags("tags=a\\ , b,,"), ["a ", "b", "", ""]);
+        assert_eq!(tags("tags="), Vec::<String>::new());
+        assert_eq!(tags("tags=[]"), Vec::<String>::new());
+        assert_eq!(tags("tags=[ a, b ]"), ["a", "b"]);
+        assert_eq!(tags("tags=\\[a, b]"), ["[a", "b]"]);
+        assert_eq!(tags("tags=[a\\]"), ["[a]"]);
+        assert_eq!(tags("tags=caf\\u00e9, \\u0041"), ["café", "A"]);
+
+        let c: Config = from_str("tags=a\nports=[80, 443]\n").unwrap();
+        assert_eq!(c.ports, [80, 443]);
+        let c: Config = from_str("tags=a\nports= \n").unwrap();
+        assert_eq!(c.ports, []);
+
+        let options = PropertiesOptions::new().list_delimiter(";");
+        let c: Config = from_str_with("tags=a,b; c\\;d", &options).unwrap();
+        assert_eq!(c.tags, ["a,b", "c;d"]);
+        let options = PropertiesOptions::new().list_delimiter("||");
+        let c: Config = from_str_with("tags=a || b\\||c", &options).unwrap();
+        assert_eq!(c.tags, ["a", "b||c"]);
+        let options = PropertiesOptions::new().list_delimiter("0");
+        let c: Config = from_str_with("tags=\\u0041 0 \\u00420", &options).unwrap();
+        assert_eq!(c.tags, ["A", "B", ""]);
+    }
+
+    #[test]
+    fn nested_lists_and_inline_maps() {
+        use std::collections::HashMap;
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            matrix: Vec<Vec<u32>>,
+            weights: HashMap<String, u32>,
+            db: Db,
+        }
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Db {
+            host: String,
+            port: u16,
+        }
+        let input = "matrix=1,2;3, 4;\nweights=a:1, b\\:c : 2\ndb=host:x\\,y,port:5\n";
+        let options = PropertiesOptions::new().secondary_delimiter(";");
+        let c: Config = from_str_with(input, &options).unwrap();
+        assert_eq!(c.matrix, [vec![1, 2], vec![3, 4], vec![]]);
+        assert_eq!(c.weights.len(), 2);
+        assert_eq!((c.weights["a"], c.weights["b:c"]), (1, 2));
+        assert_eq!(
+            c.db,
+            Db {
+                host: "x,y".to_string(),
+                port: 5
+            }
+        );
+
+        let input = "matrix=[1];[]\nweights=\ndb=host:,port:0";
+        let c: Config = from_str_with(input, &options).unwrap();
+        assert_eq!(c.matrix, [vec![1], vec![]]);
+        assert!(c.weights.is_empty());
+
+        let err = from_str::<Config>("matrix=1\nweights=a:1,b\ndb.host=x\ndb.port=1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid value `b`, expected key:value at line 2, column 9 (key weights)"
+        );
+        let err = from_str::<Config>("matrix=1\nweights=a:x\ndb.host=x\ndb.port=1").unwrap_err();
+        assert_eq!(err.key(), Some("weights.a"));
+    }
+
+    #[test]
+    fn secondary_delimiter_by_type() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Config {
+            tags: Vec<String>,
+            matrix: Vec<Vec<u32>>,
+        }
+        let options = PropertiesOptions::new().secondary_delimiter(";");
+        let c: Config = from_str_with("tags=a;b,c\nmatrix=1,2,3\n", &options).unwrap();
+        assert_eq!(c.tags, ["a;b", "c"]);
+        assert_eq!(c.matrix, [[1, 2, 3]]);
+
+        #[derive(Deserialize, Debug)]
+        struct Ports {
+            #[allow(dead_code)]
+            ports: Vec<u16>,
+        }
+        let err = from_str_with::<Ports>("ports=80;443\n", &options).unwrap_err();
+        assert_eq!(err.key(), Some("ports[0]"));
+    }
 }
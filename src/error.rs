@@ -8,6 +8,57 @@ pub enum Error {
     IO(::std::io::Error),
     Utf8(::std::str::Utf8Error),
     Parse(ParseError),
+    /// A value that doesn't parse as the type expected.
+    InvalidValue {
+        /// The value as read, after unescaping.
+        value: String,
+        /// Name of the type expected, such as `u16`.
+        expected: &'static str,
+    },
+    /// A value of a shape properties can't represent.
+    Unsupported {
+        /// What was found, such as `"struct as a map key"`.
+        kind: &'static str,
+        /// Dotted key of the value, if it has one.
+        key: Option<String>,
+    },
+    /// An error found at a position in the input.
+    Located {
+        /// Physical line number, starting at 1.
+        line: usize,
+        /// Column in characters, starting at 1.
+        column: usize,
+        /// Dotted key being read, if known.
+        key: Option<String>,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Line of the input the error was found on, starting at 1.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Error::Located { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+
+    /// Column of the input the error was found at, in characters starting
+    /// at 1.
+    pub fn column(&self) -> Option<usize> {
+        match self {
+            Error::Located { column, .. } => Some(*column),
+            _ => None,
+        }
+    }
+
+    /// Dotted key being read or written when the error happened.
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Error::Located { key, .. } | Error::Unsupported { key, .. } => key.as_deref(),
+            _ => None,
+        }
+    }
 }
 
 impl de::Error for Error {
@@ -22,11 +73,58 @@ impl ser::Error for Error {
     }
 }
 
-impl std::error::Error for Error {}
+/// Messages include everything but the underlying `io::Error` or
+/// `Utf8Error`, which is left to `source()` so error reporters don't print
+/// it twice. A located error shows the message of the error it wraps, so it
+/// passes on that error's source rather than the error itself.
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IO(err) => Some(err),
+            Error::Utf8(err) => Some(err),
+            Error::Located { error, .. } => error.source(),
+            Error::Custom(_)
+            | Error::Parse(_)
+            | Error::InvalidValue { .. }
+            | Error::Unsupported { .. } => None,
+        }
+    }
+}
 
 impl Display for Error {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str(std::error::Error::description(self))
+        match self {
+            Error::Custom(msg) => formatter.write_str(msg),
+            Error::IO(_) => formatter.write_str("I/O error"),
+            Error::Utf8(_) => formatter.write_str("Invalid UTF-8"),
+            Error::Parse(err) => write!(formatter, "Parse error: {}", err),
+            Error::InvalidValue { value, expected } => {
+                write!(
+                    formatter,
+                    "Invalid value `{}`, expected {}",
+                    value, expected
+                )
+            }
+            Error::Unsupported {
+                kind,
+                key: Some(key),
+            } => {
+                write!(formatter, "Unsupported {} at key {}", kind, key)
+            }
+            Error::Unsupported { kind, key: None } => write!(formatter, "Unsupported {}", kind),
+            Error::Located {
+                line,
+                column,
+                key,
+                error,
+            } => {
+                write!(formatter, "{} at line {}, column {}", error, line, column)?;
+                match key {
+                    Some(key) => write!(formatter, " (key {})", key),
+                    None => Ok(()),
+                }
+            }
+        }
     }
 }
 
@@ -48,9 +146,123 @@ impl From<::std::io::Error> for Error {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ParseError {
+    /// Not produced by the deserializer, which reads a line with nothing
+    /// before its separator as the empty key, as `java.util.Properties` does.
     NoKey,
     NoValue,
     InvalidValue,
+    InvalidUnicodeEscape,
+    /// A key assigned again with `DuplicateKeys::Error`.
+    DuplicateKey {
+        first_line: usize,
+    },
+}
+
+impl std::error::Error for ParseError {}
+
+impl Display for ParseError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::NoKey => formatter.write_str("Missing key"),
+            ParseError::NoValue => formatter.write_str("Missing value"),
+            ParseError::InvalidValue => formatter.write_str("Invalid value"),
+            ParseError::InvalidUnicodeEscape => {
+                formatter.write_str("Invalid unicode escape, expected \\uXXXX")
+            }
+            ParseError::DuplicateKey { first_line } => {
+                write!(formatter, "Duplicate key, first set on line {}", first_line)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn messages() {
+        assert_eq!(Error::Custom("custom".to_string()).to_string(), "custom");
+        assert_eq!(
+            Error::from(ParseError::NoValue).to_string(),
+            "Parse error: Missing value"
+        );
+        assert_eq!(
+            ParseError::InvalidUnicodeEscape.to_string(),
+            "Invalid unicode escape, expected \\uXXXX"
+        );
+        assert_eq!(
+            Error::Unsupported {
+                kind: "value without a key",
+                key: None,
+            }
+            .to_string(),
+            "Unsupported value without a key"
+        );
+        assert_eq!(
+            Error::Unsupported {
+                kind: "struct or map as a map key",
+                key: Some("a.b".to_string()),
+            }
+            .to_string(),
+            "Unsupported struct or map as a map key at key a.b"
+        );
+        let located = Error::Located {
+            line: 3,
+            column: 5,
+            key: Some("db.port".to_string()),
+            error: Box::new(ParseError::InvalidValue.into()),
+        };
+        assert_eq!(
+            located.to_string(),
+            "Parse error: Invalid value at line 3, column 5 (key db.port)"
+        );
+        assert_eq!(
+            (located.line(), located.column(), located.key()),
+            (Some(3), Some(5), Some("db.port"))
+        );
+        assert!(located.source().is_none());
+        assert_eq!(
+            Error::InvalidValue {
+                value: "80x".to_string(),
+                expected: "u16",
+            }
+            .to_string(),
+            "Invalid value `80x`, expected u16"
+        );
+        let io = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "eof");
+        assert_eq!(Error::from(io).to_string(), "I/O error");
+        let utf8 = std::str::from_utf8(&[b'a', 0xff][..]).unwrap_err();
+        assert_eq!(Error::from(utf8).to_string(), "Invalid UTF-8");
+    }
+
+    #[test]
+    fn sources() {
+        let io = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "eof");
+        let err = Error::from(io);
+        let source = err.source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+
+        let utf8 = std::str::from_utf8(&[b'a', 0xff][..]).unwrap_err();
+        let err = Error::from(utf8);
+        assert!(err.source().unwrap().is::<std::str::Utf8Error>());
+
+        let located = Error::Located {
+            line: 1,
+            column: 1,
+            key: None,
+            error: Box::new(err),
+        };
+        assert!(located.source().unwrap().is::<std::str::Utf8Error>());
+
+        assert!(Error::from(ParseError::NoKey).source().is_none());
+        assert!(ParseError::NoKey.source().is_none());
+        assert!(Error::Custom("custom".to_string()).source().is_none());
+    }
 }
@@ -1,21 +1,694 @@
+//! Serde support for Java `.properties` files, with nested structs and maps
+//! written as dotted keys such as `db.host`.
+//!
+//! # Flatten and untagged
+//!
+//! Serde buffers `#[serde(flatten)]` and `#[serde(untagged)]` values before
+//! it knows their types, so they're read without one: a value takes the type
+//! it's inferred as, as described on [`PropertiesOptions::infer_types`], and
+//! keys nested below `labels`, such as `labels.team`, are buffered as a map
+//! under `labels`. A flattened `HashMap<String, String>` catch-all therefore
+//! only holds values without a dot in their key, and needs `infer_types(false)`
+//! to read `3` or `true` as a string. Otherwise these fail with
+//! ``invalid type: integer `3`, expected a string`` or
+//! `invalid type: map, expected a string`.
+//! A catch-all whose values can be maps reads both:
+//!
+//! ```
+//! use serde::Deserialize;
+//! use std::collections::HashMap;
+//!
+//! #[derive(Deserialize, Debug, PartialEq)]
+//! #[serde(untagged)]
+//! enum Value {
+//!     Integer(u64),
+//!     Text(String),
+//!     Group(HashMap<String, Value>),
+//! }
+//!
+//! #[derive(Deserialize)]
+//! struct Config {
+//!     name: String,
+//!     #[serde(flatten)]
+//!     rest: HashMap<String, Value>,
+//! }
+//!
+//! let input = "name=orders\nretries=3\nlabels.team=core\n";
+//! let config: Config = serde_properties::from_str(input).unwrap();
+//! assert_eq!(config.name, "orders");
+//! assert_eq!(config.rest["retries"], Value::Integer(3));
+//! let labels = HashMap::from([("team".to_string(), Value::Text("core".to_string()))]);
+//! assert_eq!(config.rest["labels"], Value::Group(labels));
+//! ```
+
 extern crate serde;
 
 mod de;
 mod error;
+mod options;
+mod report;
 mod ser;
 
 pub const DEFAULT_ESCAPE: char = '\\';
 pub const DEFAULT_SEPARATOR: char = '=';
+pub const DEFAULT_LIST_DELIMITER: &str = ",";
+
+/// Separates the segments of a dotted key such as `db.host`.
+const KEY_SEPARATOR: char = '.';
+
+/// Separates the key from the value of an entry in an inline map such as
+/// `weights=a:1,b:2`.
+const INLINE_MAP_SEPARATOR: char = ':';
 
-pub use de::{from_buf_read, from_bytes, from_str, from_reader};
-pub use ser::to_writer;
+pub use de::{from_buf_read, from_bytes, from_reader, from_str, from_str_with, Deserializer};
+pub use ser::{to_string, to_string_with, to_writer, to_writer_with, Serializer};
 
 pub use error::{Error, ParseError};
+pub use options::{BinaryEncoding, DuplicateKeys, IndexStyle, LineEnding, PropertiesOptions};
+pub use report::Report;
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::fmt::Debug;
+
+    fn round_trip<T>(value: &T) -> T
+    where
+        T: Serialize + for<'de> Deserialize<'de> + PartialEq + Debug,
+    {
+        let mut buf = Vec::new();
+        to_writer(&mut buf, value).unwrap();
+        let s = String::from_utf8(buf).unwrap();
+        let back: T = from_str(&s).unwrap();
+        assert_eq!(&back, value, "{}", s);
+        back
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn round_trip_line_endings() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Config {
+            name: String,
+            tags: Vec<String>,
+            port: u16,
+        }
+        let c = Config {
+            name: "a\rb\nc".to_string(),
+            tags: vec!["x".to_string(), "y".to_string()],
+            port: 80,
+        };
+        for line_ending in [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr] {
+            let options = PropertiesOptions::new().line_ending(line_ending);
+            let s = to_string_with(&c, &options).unwrap();
+            assert_eq!(from_str_with::<Config>(&s, &options).unwrap(), c, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn round_trip_nested() {
+        use std::collections::HashMap;
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Config {
+            name: String,
+            server: Server,
+            labels: HashMap<String, String>,
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Server {
+            host: String,
+            port: u16,
+            tls: Tls,
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Tls {
+            enabled: bool,
+            cert: Option<String>,
+        }
+        let mut labels = HashMap::new();
+        labels.insert("app.kubernetes.io/name".to_string(), "orders".to_string());
+        labels.insert("team".to_string(), " core ".to_string());
+        round_trip(&Config {
+            name: "orders: main=1".to_string(),
+            server: Server {
+                host: "0.0.0.0".to_string(),
+                port: 8080,
+                tls: Tls {
+                    enabled: true,
+                    cert: Some("/etc/tls/cert.pem".to_string()),
+                },
+            },
+            labels,
+        });
+    }
+
+    #[test]
+    fn round_trip_empty_structs() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Config {
+            empty: Empty,
+            mode: Mode,
+            port: u16,
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Empty {}
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        enum Mode {
+            Idle {},
+        }
+        round_trip(&Config {
+            empty: Empty {},
+            mode: Mode::Idle {},
+            port: 1,
+        });
+    }
+
+    #[test]
+    fn round_trip_indexed() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Config {
+            servers: Vec<Server>,
+            groups: Vec<Vec<Server>>,
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Server {
+            host: String,
+            port: u16,
+        }
+        let server = |host: &str, port| Server {
+            host: host.to_string(),
+            port,
+        };
+        round_trip(&Config {
+            servers: vec![server("a", 1), server("b", 2), server("c", 3)],
+            groups: vec![vec![server("d", 4)], vec![server("e", 5), server("f", 6)]],
+        });
+    }
+
+    #[test]
+    fn round_trip_maps_of_structs() {
+        use std::collections::{BTreeMap, HashMap};
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Config {
+            datasource: HashMap<String, DataSource>,
+            by_port: BTreeMap<u16, DataSource>,
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct DataSource {
+            url: String,
+            replicas: Vec<String>,
+        }
+        let source = |url: &str| DataSource {
+            url: url.to_string(),
+            replicas: vec!["r1".to_string(), "r2".to_string()],
+        };
+        let mut datasource = HashMap::new();
+        datasource.insert("primary".to_string(), source("a"));
+        datasource.insert("reporting.eu".to_string(), source("b"));
+        let mut by_port = BTreeMap::new();
+        by_port.insert(5432, source("c"));
+        round_trip(&Config {
+            datasource,
+            by_port,
+        });
+        round_trip(&Config {
+            datasource: HashMap::new(),
+            by_port: BTreeMap::new(),
+        });
+    }
+
+    #[test]
+    fn round_trip_unit_enums() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        enum Level {
+            Debug,
+            Info,
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Config {
+            level: Level,
+            levels: Vec<Level>,
+        }
+        round_trip(&Config {
+            level: Level::Debug,
+            levels: vec![Level::Info, Level::Debug],
+        });
+    }
+
+    #[test]
+    fn round_trip_data_enums() {
+        use std::collections::BTreeMap;
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        enum Transport {
+            Local,
+            Udp(u16),
+            Pair(String, u16),
+            Tcp { host: String, port: u16 },
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Config {
+            transport: Transport,
+            transports: Vec<Transport>,
+            named: BTreeMap<String, Transport>,
+        }
+        let mut named = BTreeMap::new();
+        named.insert("dns".to_string(), Transport::Udp(53));
+        named.insert("local".to_string(), Transport::Local);
+        round_trip(&Config {
+            transport: Transport::Tcp {
+                host: "localhost".to_string(),
+                port: 8080,
+            },
+            transports: vec![
+                Transport::Local,
+                Transport::Pair("localhost".to_string(), 80),
+                Transport::Udp(53),
+            ],
+            named,
+        });
+    }
+
+    #[test]
+    fn round_trip_tagged_enums() {
+        use std::collections::BTreeMap;
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        #[serde(tag = "type")]
+        enum Storage {
+            Memory,
+            S3 { bucket: String, region: String },
+            Disk(Disk),
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Disk {
+            path: String,
+            quota: u64,
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        #[serde(tag = "kind", content = "spec")]
+        enum Auth {
+            None,
+            Token(String),
+            Basic { user: String, password: String },
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Config {
+            storage: BTreeMap<String, Storage>,
+            auth: Vec<Auth>,
+        }
+        let mut storage = BTreeMap::new();
+        storage.insert("cache".to_string(), Storage::Memory);
+        storage.insert(
+            "archive".to_string(),
+            Storage::S3 {
+                bucket: "plugins".to_string(),
+                region: "eu-west-1".to_string(),
+            },
+        );
+        storage.insert(
+            "local".to_string(),
+            Storage::Disk(Disk {
+                path: "/var/lib".to_string(),
+                quota: 1024,
+            }),
+        );
+        round_trip(&Config {
+            storage,
+            auth: vec![
+                Auth::None,
+                Auth::Token("abc".to_string()),
+                Auth::Basic {
+                    user: "admin".to_string(),
+                    password: "secret".to_string(),
+                },
+            ],
+        });
+    }
+
+    #[test]
+    fn round_trip_flatten_and_untagged() {
+        use std::collections::BTreeMap;
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Config {
+            name: String,
+            #[serde(flatten)]
+            db: Db,
+            #[serde(flatten)]
+            extra: BTreeMap<String, Value>,
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Db {
+            host: String,
+            port: u16,
+            timeout: Option<f64>,
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        #[serde(untagged)]
+        enum Value {
+            Flag(bool),
+            Number(i64),
+            Text(String),
+            Group(BTreeMap<String, Value>),
+        }
+        let mut group = BTreeMap::new();
+        group.insert("team".to_string(), Value::Text("core".to_string()));
+        group.insert("replicas".to_string(), Value::Number(-2));
+        let mut extra = BTreeMap::new();
+        extra.insert("debug".to_string(), Value::Flag(true));
+        extra.insert("labels".to_string(), Value::Group(group));
+        round_trip(&Config {
+            name: "orders".to_string(),
+            db: Db {
+                host: "db.internal".to_string(),
+                port: 5432,
+                timeout: Some(1.5),
+            },
+            extra,
+        });
+    }
+
+    #[test]
+    fn round_trip_tuples() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Range(u16, u16);
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        enum Shape {
+            Circle(i32, i32, u32),
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Config {
+            ports: Range,
+            scale: (f64, f64),
+            shapes: Vec<Shape>,
+        }
+        round_trip(&Config {
+            ports: Range(8000, 8080),
+            scale: (0.5, 2.0),
+            shapes: vec![Shape::Circle(0, 0, 5), Shape::Circle(1, -1, 2)],
+        });
+    }
+
+    #[test]
+    fn round_trip_bytes() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Keys {
+            #[serde(with = "serde_bytes")]
+            signing: Vec<u8>,
+            #[serde(with = "serde_bytes")]
+            backup: Option<Vec<u8>>,
+            salts: Vec<serde_bytes::ByteBuf>,
+        }
+        round_trip(&Keys {
+            signing: (0..=255).collect(),
+            backup: Some(b"\x00=:#!\\ ".to_vec()),
+            salts: vec![
+                serde_bytes::ByteBuf::from(vec![1, 2, 3]),
+                serde_bytes::ByteBuf::from(vec![255; 7]),
+            ],
+        });
+    }
+
+    /// Holds every type of the serde data model, so serializing and
+    /// deserializing it goes through every `Serializer` and `Deserializer`
+    /// method.
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Everything {
+        bool: bool,
+        i8: i8,
+        i16: i16,
+        i32: i32,
+        i64: i64,
+        i128: i128,
+        u8: u8,
+        u16: u16,
+        u32: u32,
+        u64: u64,
+        u128: u128,
+        f32: f32,
+        f64: f64,
+        char: char,
+        string: String,
+        bytes: serde_bytes::ByteBuf,
+        none: Option<u8>,
+        some: Option<u8>,
+        unit: (),
+        unit_struct: UnitStruct,
+        newtype_struct: NewtypeStruct,
+        seq: Vec<u8>,
+        tuple: (u8, String),
+        tuple_struct: TupleStruct,
+        map: std::collections::BTreeMap<String, u8>,
+        unit_variant: Variants,
+        newtype_variant: Variants,
+        tuple_variant: Variants,
+        struct_variant: Variants,
+        untagged: Untagged,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct UnitStruct;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct NewtypeStruct(u16);
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct TupleStruct(i8, bool);
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Variants {
+        Unit,
+        Newtype(u8),
+        Tuple(u8, u8),
+        Struct { a: u8 },
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[serde(untagged)]
+    enum Untagged {
+        Number(u8),
+        Text(String),
+    }
+
+    fn everything() -> Everything {
+        let mut map = std::collections::BTreeMap::new();
+        map.insert("a".to_string(), 1);
+        Everything {
+            bool: true,
+            i8: -8,
+            i16: -16,
+            i32: -32,
+            i64: -64,
+            i128: -128,
+            u8: 8,
+            u16: 16,
+            u32: 32,
+            u64: 64,
+            u128: 128,
+            f32: 3.5,
+            f64: -0.25,
+            char: '=',
+            string: "text".to_string(),
+            bytes: serde_bytes::ByteBuf::from(vec![0, 255]),
+            none: None,
+            some: Some(1),
+            unit: (),
+            unit_struct: UnitStruct,
+            newtype_struct: NewtypeStruct(7),
+            seq: vec![1, 2],
+            tuple: (3, "t".to_string()),
+            tuple_struct: TupleStruct(-1, false),
+            map,
+            unit_variant: Variants::Unit,
+            newtype_variant: Variants::Newtype(1),
+            tuple_variant: Variants::Tuple(1, 2),
+            struct_variant: Variants::Struct { a: 1 },
+            untagged: Untagged::Text("u".to_string()),
+        }
+    }
+
+    #[test]
+    fn round_trip_everything() {
+        round_trip(&everything());
+    }
+
+    /// Feeds every method malformed values and unsupported shapes, which must
+    /// all come back as errors rather than panics.
+    #[test]
+    fn no_panics() {
+        let mut buf = Vec::new();
+        to_writer(&mut buf, &everything()).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let bad_values = [
+            "", "x", "-1", "1.5", "1,2,3", "\\uZZZZ", "\\u12", "\\", "Unknown", "[0]", "=",
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            let key = &line[..line.find('=').unwrap()];
+            let mut variants: Vec<String> = bad_values
+                .iter()
+                .map(|value| format!("{}={}", key, value))
+                .collect();
+            variants.push(format!("{}.nested=1", key));
+            variants.push(format!("{}[0]=1\n{}[2]=1", key, key));
+            variants.push(format!("{}.Unknown.a=1", key));
+            variants.push(String::new());
+            for variant in variants {
+                let mut input = lines.clone();
+                input[i] = &variant;
+                let _ = from_str::<Everything>(&input.join("\n"));
+            }
+        }
+        for input in &[
+            "", "=", "a", "\\", "[0]=1", ".=.", "a..b=1", "a[=1", "a[x]=1",
+        ] {
+            let _ = from_str::<Everything>(input);
+            let _ = from_str::<std::collections::HashMap<String, Variants>>(input);
+            let _ = from_str::<Vec<Untagged>>(input);
+            let _ = from_str::<(u8, u8)>(input);
+            let _ = from_str::<u8>(input);
+            let _ = from_str::<()>(input);
+        }
+
+        match to_writer(Vec::new(), &1u8) {
+            Err(Error::Unsupported { kind, key: None }) => assert_eq!(kind, "value without a key"),
+            r => panic!("unexpected {:?}", r),
+        }
+        #[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+        struct Key {
+            a: u8,
+        }
+        let mut map = std::collections::BTreeMap::new();
+        map.insert(Key { a: 1 }, 1);
+        match to_writer(Vec::new(), &map) {
+            Err(Error::Unsupported { kind, key: None }) => {
+                assert_eq!(kind, "struct or map as a map key")
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+        let mut outer = std::collections::BTreeMap::new();
+        outer.insert("outer", map);
+        match to_writer(Vec::new(), &outer) {
+            Err(Error::Unsupported { key: Some(key), .. }) => assert_eq!(key, "outer"),
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+        use std::collections::BTreeMap;
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+        struct Lists {
+            strings: Vec<String>,
+            numbers: Vec<i64>,
+            floats: Vec<f64>,
+            flags: Vec<bool>,
+            chars: Vec<char>,
+            options: Vec<Option<u8>>,
+            tuple: (String, i32, char),
+            pairs: Vec<(u8, String)>,
+            nested: Vec<Vec<u16>>,
+            structs: Vec<Item>,
+            grid: Vec<Vec<String>>,
+            mixed: (u8, Vec<String>),
+            weights: BTreeMap<String, i32>,
+            groups: BTreeMap<String, Vec<u8>>,
+        }
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+        struct Item {
+            name: String,
+            tags: Vec<String>,
+        }
+
+        fn floats() -> impl Strategy<Value = f64> {
+            prop::num::f64::NORMAL | prop::num::f64::SUBNORMAL | prop::num::f64::ZERO
+        }
+
+        prop_compose! {
+            fn items()(name in ".*", tags in prop::collection::vec(".*", 0..4)) -> Item {
+                Item { name, tags }
+            }
+        }
+
+        prop_compose! {
+            fn lists()(
+                strings in prop::collection::vec(".*", 0..5),
+                numbers in prop::collection::vec(any::<i64>(), 0..5),
+                floats in prop::collection::vec(floats(), 0..5),
+                flags in prop::collection::vec(any::<bool>(), 0..5),
+                chars in prop::collection::vec(any::<char>(), 0..5),
+                options in prop::collection::vec(any::<Option<u8>>(), 0..5),
+                tuple in (".*", any::<i32>(), any::<char>()),
+                pairs in prop::collection::vec((any::<u8>(), ".*"), 0..3),
+                nested in prop::collection::vec(prop::collection::vec(any::<u16>(), 0..3), 0..3),
+                structs in prop::collection::vec(items(), 0..3),
+                grid in prop::collection::vec(prop::collection::vec(".*", 0..3), 0..3),
+                mixed in (any::<u8>(), prop::collection::vec(".*", 0..3)),
+                weights in prop::collection::btree_map(".+", any::<i32>(), 0..4),
+                groups in prop::collection::btree_map(
+                    "[a-z]+",
+                    prop::collection::vec(any::<u8>(), 0..3),
+                    0..3,
+                ),
+            ) -> Lists {
+                Lists {
+                    strings,
+                    numbers,
+                    floats,
+                    flags,
+                    chars,
+                    options,
+                    tuple,
+                    pairs,
+                    nested,
+                    structs,
+                    grid,
+                    mixed,
+                    weights,
+                    groups,
+                }
+            }
+        }
+
+        fn options() -> impl Strategy<Value = PropertiesOptions> {
+            (
+                prop_oneof![Just(","), Just(";"), Just("||"), Just("::")],
+                prop::option::of(prop_oneof![Just("/"), Just("&&")]),
+                any::<bool>(),
+                any::<bool>(),
+                prop_oneof![Just(IndexStyle::Brackets), Just(IndexStyle::Dots)],
+            )
+                .prop_map(|(delimiter, secondary, repeat, inline, index_style)| {
+                    let options = PropertiesOptions::new()
+                        .list_delimiter(delimiter)
+                        .repeat_keys(repeat)
+                        .inline_maps(inline)
+                        .index_style(index_style);
+                    match secondary {
+                        Some(secondary) => options.secondary_delimiter(secondary),
+                        None => options,
+                    }
+                })
+        }
+
+        proptest! {
+            #[test]
+            fn lists_round_trip(lists in lists()) {
+                let s = to_string(&lists).unwrap();
+                prop_assert_eq!(from_str::<Lists>(&s).unwrap(), lists, "{}", s);
+            }
+
+            #[test]
+            fn lists_round_trip_with_options(lists in lists(), options in options()) {
+                let s = to_string_with(&lists, &options).unwrap();
+                prop_assert_eq!(from_str_with::<Lists>(&s, &options).unwrap(), lists, "{}", s);
+            }
+        }
+    }
 }
@@ -0,0 +1,288 @@
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+
+/// Line terminator written after each key/value pair.
+///
+/// The deserializer accepts any of them regardless of this setting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+/// How the serializer writes the keys of a sequence whose elements are structs
+/// or maps. The deserializer accepts both.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexStyle {
+    /// `servers[0].host`
+    #[default]
+    Brackets,
+    /// `servers.0.host`
+    Dots,
+}
+
+/// What the deserializer does with a key assigned more than once.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// The last value wins, as with `java.util.Properties`.
+    #[default]
+    LastWins,
+    /// The first value wins.
+    FirstWins,
+    /// Fails, naming the lines of both assignments.
+    Error,
+    /// Keeps every value. A sequence gets the elements of each value in
+    /// turn, and anything else reads the last value.
+    Collect,
+}
+
+/// Text encoding of byte strings, as serialized by `serde_bytes`. A plain
+/// `Vec<u8>` is a sequence of numbers instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BinaryEncoding {
+    /// Standard base64 alphabet with padding.
+    #[default]
+    Base64,
+    /// URL and filename safe base64 alphabet with padding.
+    Base64UrlSafe,
+    /// Lowercase hexadecimal. Either case is accepted when deserializing.
+    Hex,
+}
+
+impl BinaryEncoding {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            BinaryEncoding::Base64 | BinaryEncoding::Base64UrlSafe => "base64",
+            BinaryEncoding::Hex => "hex",
+        }
+    }
+
+    pub(crate) fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            BinaryEncoding::Base64 => base64_engine(&alphabet::STANDARD).encode(bytes),
+            BinaryEncoding::Base64UrlSafe => base64_engine(&alphabet::URL_SAFE).encode(bytes),
+            BinaryEncoding::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+
+    pub(crate) fn decode(self, s: &str) -> Result<Vec<u8>, String> {
+        match self {
+            BinaryEncoding::Base64 => base64_engine(&alphabet::STANDARD)
+                .decode(s)
+                .map_err(|e| e.to_string()),
+            BinaryEncoding::Base64UrlSafe => base64_engine(&alphabet::URL_SAFE)
+                .decode(s)
+                .map_err(|e| e.to_string()),
+            BinaryEncoding::Hex => {
+                if !s.len().is_multiple_of(2) {
+                    return Err("Odd number of digits".to_string());
+                }
+                (0..s.len())
+                    .step_by(2)
+                    .map(|i| {
+                        // `from_str_radix` alone would accept a sign, as in `+f`.
+                        s.get(i..i + 2)
+                            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+                            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                            .ok_or_else(|| format!("Invalid digits at offset {}", i))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Pads when encoding, and accepts values with or without padding.
+fn base64_engine(alphabet: &alphabet::Alphabet) -> GeneralPurpose {
+    let config =
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+    GeneralPurpose::new(alphabet, config)
+}
+
+/// Options shared by the `Serializer` and `Deserializer`.
+///
+/// ```
+/// use serde_properties::{LineEnding, PropertiesOptions};
+///
+/// let options = PropertiesOptions::new()
+///     .separator(':')
+///     .list_delimiter(";")
+///     .line_ending(LineEnding::CrLf);
+/// ```
+#[derive(Clone, Debug)]
+pub struct PropertiesOptions {
+    pub(crate) separator: char,
+    pub(crate) escape: char,
+    pub(crate) list_delimiter: String,
+    pub(crate) secondary_delimiter: Option<String>,
+    pub(crate) trim_values: bool,
+    pub(crate) line_ending: LineEnding,
+    pub(crate) index_style: IndexStyle,
+    pub(crate) case_insensitive_enums: bool,
+    pub(crate) infer_types: bool,
+    pub(crate) binary_encoding: BinaryEncoding,
+    pub(crate) duplicate_keys: DuplicateKeys,
+    pub(crate) repeat_keys: bool,
+    pub(crate) inline_maps: bool,
+}
+
+impl PropertiesOptions {
+    pub fn new() -> Self {
+        PropertiesOptions {
+            separator: crate::DEFAULT_SEPARATOR,
+            escape: crate::DEFAULT_ESCAPE,
+            list_delimiter: crate::DEFAULT_LIST_DELIMITER.to_string(),
+            secondary_delimiter: None,
+            trim_values: false,
+            line_ending: LineEnding::default(),
+            index_style: IndexStyle::default(),
+            case_insensitive_enums: false,
+            infer_types: true,
+            binary_encoding: BinaryEncoding::default(),
+            duplicate_keys: DuplicateKeys::default(),
+            repeat_keys: false,
+            inline_maps: false,
+        }
+    }
+
+    /// Separator written between keys and values. The deserializer accepts
+    /// it in addition to `=`, `:` and whitespace.
+    pub fn separator(mut self, separator: char) -> Self {
+        self.separator = separator;
+        self
+    }
+
+    /// Character starting an escape sequence.
+    pub fn escape(mut self, escape: char) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    /// Delimiter between the elements of a sequence held in a single value.
+    /// Escape it to use it within an element, as in `a\,b`. The serializer
+    /// rejects an empty delimiter, and one starting with `t`, `n`, `r`, `f` or
+    /// `u`, whose escaped form reads as an escape sequence such as `\t`.
+    pub fn list_delimiter<S: Into<String>>(mut self, delimiter: S) -> Self {
+        self.list_delimiter = delimiter.into();
+        self
+    }
+
+    /// Delimiter between the inner sequences of a sequence of sequences held
+    /// in a single value, as in `matrix=1,2;3,4`. A value is only split on it
+    /// if its first element is read as a sequence, so flat sequences ignore
+    /// it. Unset by default, in which case such sequences are written as
+    /// indexed keys. The serializer rejects the same delimiters as for
+    /// `list_delimiter`.
+    pub fn secondary_delimiter<S: Into<String>>(mut self, delimiter: S) -> Self {
+        self.secondary_delimiter = Some(delimiter.into());
+        self
+    }
+
+    /// Trims unescaped trailing whitespace from values. By default values keep
+    /// their trailing whitespace, as with `java.util.Properties`.
+    pub fn trim_values(mut self, trim: bool) -> Self {
+        self.trim_values = trim;
+        self
+    }
+
+    /// Line terminator written by the serializer.
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Key layout written by the serializer for sequences of structs or maps.
+    pub fn index_style(mut self, index_style: IndexStyle) -> Self {
+        self.index_style = index_style;
+        self
+    }
+
+    /// Matches enum variant names ignoring ASCII case when deserializing.
+    pub fn case_insensitive_enums(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive_enums = case_insensitive;
+        self
+    }
+
+    /// Infers booleans and numbers from values read without a type hint, as
+    /// through `#[serde(flatten)]` or `#[serde(untagged)]`. Enabled by
+    /// default. Disable it to read every such value as a string, as a
+    /// flattened `HashMap<String, String>` needs.
+    ///
+    /// Serde buffers every flattened value the same way, so this can't suit
+    /// both a string catch-all and a typed field of another flattened
+    /// struct: with inference disabled, a flattened `Db { port: u16 }` next
+    /// to such a map fails to read. Fields of the struct itself keep their
+    /// types either way.
+    pub fn infer_types(mut self, infer: bool) -> Self {
+        self.infer_types = infer;
+        self
+    }
+
+    /// Encoding of byte strings, such as `serde_bytes` fields.
+    pub fn binary_encoding(mut self, encoding: BinaryEncoding) -> Self {
+        self.binary_encoding = encoding;
+        self
+    }
+
+    /// What the deserializer does with a key assigned more than once, in
+    /// structs and maps alike.
+    pub fn duplicate_keys(mut self, policy: DuplicateKeys) -> Self {
+        self.duplicate_keys = policy;
+        self
+    }
+
+    /// Writes each element of a sequence on its own line under the same key,
+    /// as Apache Commons Configuration does, instead of joining them with the
+    /// list delimiter. When deserializing, repeated keys are collected as with
+    /// `DuplicateKeys::Collect`, whatever the duplicate key policy.
+    ///
+    /// ```
+    /// use serde_properties::PropertiesOptions;
+    ///
+    /// #[derive(serde::Deserialize, serde::Serialize, Debug, PartialEq)]
+    /// struct Config {
+    ///     allow: Vec<String>,
+    /// }
+    ///
+    /// let options = PropertiesOptions::new().repeat_keys(true);
+    /// let config: Config =
+    ///     serde_properties::from_str_with("allow=a\nallow=b,c\n", &options).unwrap();
+    /// assert_eq!(config.allow, ["a", "b", "c"]);
+    ///
+    /// let mut buf = Vec::new();
+    /// serde_properties::to_writer_with(&mut buf, &config, &options).unwrap();
+    /// assert_eq!(buf, b"allow=a\nallow=b\nallow=c\n");
+    /// ```
+    pub fn repeat_keys(mut self, repeat: bool) -> Self {
+        self.repeat_keys = repeat;
+        self
+    }
+
+    /// Writes maps as a single value of `key:value` entries separated by the
+    /// list delimiter, as in `weights=a:1,b:2`, instead of as nested keys.
+    /// Maps holding structs, maps or sequences are still written as nested
+    /// keys. The deserializer accepts both regardless.
+    pub fn inline_maps(mut self, inline: bool) -> Self {
+        self.inline_maps = inline;
+        self
+    }
+}
+
+impl Default for PropertiesOptions {
+    fn default() -> Self {
+        PropertiesOptions::new()
+    }
+}
@@ -0,0 +1,243 @@
+use crate::error::{Error, ParseError};
+use crate::options::PropertiesOptions;
+use std::fmt::{self, Display};
+
+/// An error rendered against the input it was read from: the offending line
+/// with a caret under the column, and a hint where one applies.
+///
+/// ```
+/// #[derive(serde::Deserialize, Debug)]
+/// struct Config {
+///     port: u16,
+/// }
+///
+/// let input = "port = 80x\n";
+/// let err = serde_properties::from_str::<Config>(input).unwrap_err();
+/// assert_eq!(
+///     err.report(input).to_string(),
+///     "error: Invalid value `80x`, expected u16\n \
+///      --> line 1, column 8 (key port)\n  \
+///        |\n\
+///      1 | port = 80x\n  \
+///        |        ^^^\n  \
+///        = hint: port expects a value of type u16"
+/// );
+/// ```
+pub struct Report<'a> {
+    error: &'a Error,
+    input: &'a str,
+    separator: char,
+    escape: char,
+}
+
+impl Error {
+    /// Renders this error against the `input` it was read from, for showing
+    /// to whoever has to fix the input.
+    pub fn report<'a>(&'a self, input: &'a str) -> Report<'a> {
+        Report {
+            error: self,
+            input,
+            separator: crate::DEFAULT_SEPARATOR,
+            escape: crate::DEFAULT_ESCAPE,
+        }
+    }
+
+    /// Like `report`, for `input` read with `options`.
+    pub fn report_with<'a>(&'a self, input: &'a str, options: &PropertiesOptions) -> Report<'a> {
+        Report {
+            separator: options.separator,
+            escape: options.escape,
+            ..self.report(input)
+        }
+    }
+}
+
+impl Report<'_> {
+    /// The error without its position, which the report shows separately.
+    fn cause(&self) -> &Error {
+        match self.error {
+            Error::Located { error, .. } => error,
+            error => error,
+        }
+    }
+
+    fn hint(&self, text: &str) -> Option<String> {
+        match self.cause() {
+            Error::InvalidValue { value, .. } if value.is_empty() && !self.has_separator(text) => {
+                Some(format!(
+                    "No separator found, write the line as key{}value",
+                    self.separator
+                ))
+            }
+            Error::Parse(ParseError::DuplicateKey { .. }) => {
+                Some("Remove one of the assignments".to_string())
+            }
+            Error::Parse(ParseError::InvalidUnicodeEscape) => Some(
+                "Write \\u and four hex digits, such as \\u00e9, with a high surrogate \
+                 followed by a low one"
+                    .to_string(),
+            ),
+            Error::InvalidValue { expected, .. } => Some(match self.error.key() {
+                Some(key) => format!("{} expects a value of type {}", key, expected),
+                None => format!("Expected a value of type {}", expected),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Number of characters to underline from the column: the rest of the
+    /// line for a bad value, or the single character at fault.
+    fn width(&self, text: &str, column: usize) -> usize {
+        match self.cause() {
+            Error::InvalidValue { .. } => text
+                .trim_end()
+                .chars()
+                .count()
+                .saturating_sub(column - 1)
+                .max(1),
+            _ => 1,
+        }
+    }
+
+    /// Whether a line has anything separating a key from a value: an
+    /// unescaped `=`, `:` or configured separator, or whitespace after the
+    /// key.
+    fn has_separator(&self, text: &str) -> bool {
+        let mut escaped = false;
+        for c in text.trim().chars() {
+            if escaped {
+                escaped = false;
+            } else if c == self.escape {
+                escaped = true;
+            } else if c == '='
+                || c == ':'
+                || c == self.separator
+                || c == ' '
+                || c == '\t'
+                || c == '\x0c'
+            {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Display for Report<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "error: {}", self.cause())?;
+        let (line, column) = match (self.error.line(), self.error.column()) {
+            (Some(line), Some(column)) => (line, column),
+            _ => return Ok(()),
+        };
+        // A bare `\r` ends a line too, as it does for the deserializer.
+        let text = self
+            .input
+            .lines()
+            .flat_map(|text| text.split('\r'))
+            .nth(line - 1)
+            .unwrap_or("");
+        let gutter = " ".repeat(line.to_string().len());
+        write!(
+            formatter,
+            "\n{}--> line {}, column {}",
+            gutter, line, column
+        )?;
+        if let Some(key) = self.error.key() {
+            write!(formatter, " (key {})", key)?;
+        }
+        // Tabs are kept so the caret lines up however they're displayed.
+        let indent: String = text
+            .chars()
+            .take(column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(
+            formatter,
+            "\n{} |\n{} | {}\n{} | {}{}",
+            gutter,
+            line,
+            text,
+            gutter,
+            indent,
+            "^".repeat(self.width(text, column))
+        )?;
+        if let Some(hint) = self.hint(text) {
+            write!(formatter, "\n{} = hint: {}", gutter, hint)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{from_str, from_str_with, PropertiesOptions};
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Config {
+        name: String,
+        port: u16,
+    }
+
+    fn report<T: for<'de> Deserialize<'de> + std::fmt::Debug>(input: &str) -> String {
+        from_str::<T>(input).unwrap_err().report(input).to_string()
+    }
+
+    #[test]
+    fn reports() {
+        assert_eq!(
+            report::<Config>("name=a\n\tport =\t80x \n"),
+            "error: Invalid value `80x `, expected u16\n \
+             --> line 2, column 9 (key port)\n  \
+               |\n\
+             2 | \tport =\t80x \n  \
+               | \t      \t^^^\n  \
+               = hint: port expects a value of type u16"
+        );
+        assert_eq!(
+            report::<Config>("name=a\nport\n"),
+            "error: Invalid value ``, expected u16\n \
+             --> line 2, column 5 (key port)\n  \
+               |\n\
+             2 | port\n  \
+               |     ^\n  \
+               = hint: No separator found, write the line as key=value"
+        );
+        let input = format!("{}key=caf\\u00g9\n", "# comment\n".repeat(9));
+        assert_eq!(
+            report::<HashMap<String, String>>(&input),
+            "error: Parse error: Invalid unicode escape, expected \\uXXXX\n  \
+              --> line 10, column 8 (key key)\n   \
+                |\n\
+             10 | key=caf\\u00g9\n   \
+                |        ^\n   \
+                = hint: Write \\u and four hex digits, such as \\u00e9, with a high surrogate \
+             followed by a low one"
+        );
+        assert_eq!(
+            report::<Config>("name=a\rport=x\r"),
+            "error: Invalid value `x`, expected u16\n \
+             --> line 2, column 6 (key port)\n  \
+               |\n\
+             2 | port=x\n  \
+               |      ^\n  \
+               = hint: port expects a value of type u16"
+        );
+        assert_eq!(report::<Config>("name=a\n"), "error: missing field `port`");
+    }
+
+    #[test]
+    fn reports_with_options() {
+        let options = PropertiesOptions::new().separator('>').escape('^');
+        let report = |input| {
+            let err = from_str_with::<Config>(input, &options).unwrap_err();
+            err.report_with(input, &options).to_string()
+        };
+        assert!(report("name>a\nport>\n").ends_with("= hint: port expects a value of type u16"));
+        assert!(report("name>a\nport\n")
+            .ends_with("= hint: No separator found, write the line as key>value"));
+    }
+}
@@ -1,18 +1,55 @@
 use crate::error::Error;
+use crate::options::{IndexStyle, PropertiesOptions};
+use crate::{INLINE_MAP_SEPARATOR, KEY_SEPARATOR};
 use serde::ser;
 use serde::Serialize;
 use std::io::Write;
-use std::str::from_utf8;
+use std::mem;
 
 pub struct Serializer<W: Write> {
     output: W,
-    separator: char,
-    escape: char,
+    options: PropertiesOptions,
+    /// Escaped dotted key of the value being serialized.
+    prefix: String,
+    /// Collects scalars into a string instead of writing them out as entries,
+    /// for map keys and sequence elements.
+    capture: Option<String>,
+    /// Raised when a struct or map is found while capturing, so a sequence
+    /// can switch to indexed keys.
+    nested: bool,
+    key: bool,
+    /// Raised while capturing the elements of a sequence joined within a
+    /// sequence element, or the values of an inline map, where no further
+    /// sequence can be joined.
+    inner: bool,
+    /// Raised once a sequence has been joined within a sequence element, so
+    /// the outer sequence joins its elements with the secondary delimiter.
+    grouped: bool,
 }
 
 pub struct SeqSerializer<'a, W: Write> {
     serializer: &'a mut Serializer<W>,
-    first: bool,
+    /// Captured elements, joined into a single value once done, each flagged
+    /// if it's a sequence already joined with the list delimiter.
+    elements: Vec<(String, bool)>,
+    len: usize,
+    /// Elements are written as indexed keys instead of a single value.
+    indexed: bool,
+    /// This sequence is joined within an element of an outer sequence.
+    group: bool,
+    /// Key length to truncate back to once done, dropping a variant name.
+    prefix_len: usize,
+}
+
+/// Serializes map entries and struct fields below the current key.
+pub struct MapSerializer<'a, W: Write> {
+    serializer: &'a mut Serializer<W>,
+    prefix_len: usize,
+    /// Captured keys and values of a map written inline, until a value turns
+    /// out not to be a scalar.
+    inline: Option<Vec<(String, String)>>,
+    /// Whether no entries have been written yet.
+    empty: bool,
 }
 
 pub fn to_writer<T, W: Write>(output: W, value: &T) -> Result<(), Error>
@@ -23,18 +60,262 @@ where
     value.serialize(&mut serializer)
 }
 
+/// Serializes `value` to a string, as `to_writer` would write it.
+pub fn to_string<T>(value: &T) -> Result<String, Error>
+where
+    T: Serialize,
+{
+    to_string_with(value, &PropertiesOptions::default())
+}
+
+pub fn to_string_with<T>(value: &T, options: &PropertiesOptions) -> Result<String, Error>
+where
+    T: Serialize,
+{
+    let mut output = Vec::new();
+    to_writer_with(&mut output, value, options)?;
+    String::from_utf8(output).map_err(|err| Error::Utf8(err.utf8_error()))
+}
+
+pub fn to_writer_with<T, W: Write>(
+    output: W,
+    value: &T,
+    options: &PropertiesOptions,
+) -> Result<(), Error>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::with_options(output, options.clone());
+    value.serialize(&mut serializer)
+}
+
 impl<W: Write> Serializer<W> {
     pub fn new(output: W) -> Serializer<W> {
+        Serializer::with_options(output, PropertiesOptions::default())
+    }
+
+    pub fn with_options(output: W, options: PropertiesOptions) -> Serializer<W> {
         Serializer {
-            output: output,
-            separator: crate::DEFAULT_SEPARATOR,
-            escape: crate::DEFAULT_ESCAPE,
+            output,
+            options,
+            prefix: String::new(),
+            capture: None,
+            nested: false,
+            key: false,
+            inner: false,
+            grouped: false,
         }
     }
+
+    fn write_separator(&mut self) -> Result<(), Error> {
+        let mut buf = [0; 4];
+        let separator = self.options.separator.encode_utf8(&mut buf);
+        self.output.write_all(separator.as_bytes())?;
+        Ok(())
+    }
+
+    fn write_line_ending(&mut self) -> Result<(), Error> {
+        self.output
+            .write_all(self.options.line_ending.as_str().as_bytes())?;
+        Ok(())
+    }
+
+    /// Writes a scalar as the value of the current key, or adds it to the
+    /// string being captured.
     fn write_value<T: AsRef<str>>(&mut self, value: T) -> Result<(), Error> {
+        if let Some(capture) = self.capture.as_mut() {
+            capture.push_str(value.as_ref());
+            return Ok(());
+        }
+        if self.prefix.is_empty() {
+            return Err(self.unsupported("value without a key"));
+        }
+        self.output.write_all(self.prefix.as_bytes())?;
+        self.write_separator()?;
         self.output.write_all(value.as_ref().as_bytes())?;
+        self.write_line_ending()
+    }
+
+    /// Appends an escaped segment to the current key, returning the previous
+    /// key length to truncate back to.
+    fn push_key(&mut self, key: &str) -> usize {
+        let len = self.prefix.len();
+        if len > 0 {
+            self.prefix.push(KEY_SEPARATOR);
+        }
+        self.prefix.push_str(key);
+        len
+    }
+
+    /// Appends a sequence index to the current key, returning the previous
+    /// key length to truncate back to.
+    fn push_index(&mut self, index: usize) -> usize {
+        match self.options.index_style {
+            IndexStyle::Brackets => {
+                let len = self.prefix.len();
+                self.prefix.push_str(&format!("[{}]", index));
+                len
+            }
+            IndexStyle::Dots => self.push_key(&index.to_string()),
+        }
+    }
+
+    /// Serializes a scalar to a string rather than to the output.
+    fn capture<T: ?Sized + Serialize>(&mut self, value: &T, key: bool) -> Result<String, Error> {
+        let outer = self.capture.replace(String::new());
+        let outer_key = mem::replace(&mut self.key, key);
+        let result = value.serialize(&mut *self);
+        self.key = outer_key;
+        let captured = mem::replace(&mut self.capture, outer);
+        result.map(|_| captured.unwrap_or_default())
+    }
+
+    fn unsupported(&self, kind: &'static str) -> Error {
+        Error::Unsupported {
+            kind,
+            key: Some(self.prefix.clone()).filter(|key| !key.is_empty()),
+        }
+    }
+
+    /// A sequence element that is itself a sequence is joined with the list
+    /// delimiter, within the secondary delimiter if one is set. Otherwise, or
+    /// any deeper, the outer sequence switches to indexed keys as it does for
+    /// struct elements. Sequences within map keys are still joined.
+    ///
+    /// Returns whether the sequence is joined within an element.
+    fn check_nested_seq(&mut self) -> Result<bool, Error> {
+        if self.capture.is_none() || self.key {
+            return Ok(false);
+        }
+        if self.options.secondary_delimiter.is_some() && !self.inner {
+            self.inner = true;
+            return Ok(true);
+        }
+        self.nested = true;
+        Err(self.unsupported("sequence as a sequence element"))
+    }
+
+    /// Nested keys can't be written while a map key or sequence element is
+    /// being captured into a single value.
+    fn check_nested(&mut self) -> Result<(), Error> {
+        if self.capture.is_some() {
+            self.nested = true;
+            return Err(self.unsupported("struct or map as a map key"));
+        }
         Ok(())
     }
+
+    /// Escapes a key or value so `java.util.Properties` reads it back
+    /// unchanged. Spaces only need escaping in keys, where they would
+    /// otherwise end the key, and at the start of values, where they would
+    /// otherwise be skipped. Dots and brackets are escaped in keys so they
+    /// aren't read as nesting or indices.
+    fn escape_str(&self, v: &str, key: bool) -> String {
+        let mut s = String::with_capacity(v.len());
+        for (i, c) in v.chars().enumerate() {
+            let escaped = match c {
+                '\t' => 't',
+                '\n' => 'n',
+                '\r' => 'r',
+                '\x0c' => 'f',
+                ' ' if key || i == 0 => ' ',
+                KEY_SEPARATOR | '[' if key => c,
+                '=' | ':' | '#' | '!' => c,
+                c if c == self.options.escape || c == self.options.separator => c,
+                c => {
+                    s.push(c);
+                    continue;
+                }
+            };
+            s.push(self.options.escape);
+            s.push(escaped);
+        }
+        s
+    }
+
+    /// Joins escaped elements into a list value the deserializer splits back
+    /// into the same elements.
+    fn escape_list<'e, I: IntoIterator<Item = &'e String>>(&self, elements: I) -> String {
+        let mut s = String::new();
+        for (i, element) in elements.into_iter().enumerate() {
+            if i > 0 {
+                s.push_str(&self.options.list_delimiter);
+            }
+            self.escape_element(&mut s, element, i == 0);
+        }
+        s
+    }
+
+    /// Joins the elements of a sequence with the secondary delimiter, where
+    /// those flagged are sequences already joined and escaped.
+    fn join_groups(&self, elements: &[(String, bool)]) -> String {
+        let delimiter = self
+            .options
+            .secondary_delimiter
+            .as_deref()
+            .unwrap_or_default();
+        let mut s = String::new();
+        for (i, (element, group)) in elements.iter().enumerate() {
+            if i > 0 {
+                s.push_str(delimiter);
+            }
+            if *group {
+                s.push_str(element);
+            } else {
+                self.escape_element(&mut s, element, i == 0);
+            }
+        }
+        s
+    }
+
+    /// Checks that the delimiters can join elements. An empty one would run
+    /// them together, and one starting with `t`, `n`, `r`, `f` or `u` would
+    /// be escaped within an element as an escape sequence such as `\t`.
+    fn check_delimiters(&self) -> Result<(), Error> {
+        let delimiters = Some(&self.options.list_delimiter)
+            .into_iter()
+            .chain(self.options.secondary_delimiter.as_ref());
+        for delimiter in delimiters {
+            match delimiter.chars().next() {
+                None => return Err(self.unsupported("empty list delimiter")),
+                Some('t' | 'n' | 'r' | 'f' | 'u') => {
+                    return Err(self.unsupported("list delimiter starting with an escape letter"))
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends an escaped element to a list value. Every character that could
+    /// start a delimiter is escaped, so none can run into the delimiters
+    /// between elements, as are trailing spaces, which would otherwise be
+    /// trimmed, and a leading `[` on the first element, which would otherwise
+    /// be read as a bracketed list.
+    fn escape_element(&self, s: &mut String, element: &str, first: bool) {
+        let escape = self.options.escape;
+        let starts = [
+            self.options.list_delimiter.chars().next(),
+            self.options
+                .secondary_delimiter
+                .as_ref()
+                .and_then(|delimiter| delimiter.chars().next()),
+        ];
+        if first && element.starts_with('[') {
+            s.push(escape);
+        }
+        let mut escaped = false;
+        for (i, c) in element.char_indices() {
+            if escaped {
+                escaped = false;
+            } else if c == escape {
+                escaped = true;
+            } else if starts.contains(&Some(c)) || (c == ' ' && i + 1 == element.len()) {
+                s.push(escape);
+            }
+            s.push(c);
+        }
+    }
 }
 
 impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
@@ -44,11 +325,11 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
 
     type SerializeSeq = SeqSerializer<'a, W>;
     type SerializeTuple = SeqSerializer<'a, W>;
-    type SerializeTupleStruct = Self;
-    type SerializeTupleVariant = Self;
-    type SerializeMap = Self;
-    type SerializeStruct = Self;
-    type SerializeStructVariant = Self;
+    type SerializeTupleStruct = SeqSerializer<'a, W>;
+    type SerializeTupleVariant = SeqSerializer<'a, W>;
+    type SerializeMap = MapSerializer<'a, W>;
+    type SerializeStruct = MapSerializer<'a, W>;
+    type SerializeStructVariant = MapSerializer<'a, W>;
 
     fn serialize_bool(self, v: bool) -> Result<(), Error> {
         self.write_value(v.to_string())
@@ -70,6 +351,10 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
         self.write_value(v.to_string())
     }
 
+    fn serialize_i128(self, v: i128) -> Result<(), Error> {
+        self.write_value(v.to_string())
+    }
+
     fn serialize_u8(self, v: u8) -> Result<(), Error> {
         self.write_value(v.to_string())
     }
@@ -86,6 +371,10 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
         self.write_value(v.to_string())
     }
 
+    fn serialize_u128(self, v: u128) -> Result<(), Error> {
+        self.write_value(v.to_string())
+    }
+
     fn serialize_f32(self, v: f32) -> Result<(), Error> {
         self.write_value(v.to_string())
     }
@@ -95,20 +384,18 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
     }
 
     fn serialize_char(self, v: char) -> Result<(), Error> {
-        self.write_value(v.to_string())
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
     }
 
     fn serialize_str(self, v: &str) -> Result<(), Error> {
-        let s = v.replace(self.escape, &format!("{}{}", self.escape, self.escape));
-        let s = s.replace(
-            self.separator,
-            &format!("{}{}", self.escape, self.separator),
-        );
+        let s = self.escape_str(v, self.key);
         self.write_value(s)
     }
 
+    /// Bytes are written with the configured `BinaryEncoding`.
     fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
-        self.write_value(from_utf8(v)?)
+        let s = self.options.binary_encoding.encode(v);
+        self.write_value(s)
     }
 
     fn serialize_none(self) -> Result<(), Error> {
@@ -123,11 +410,11 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
     }
 
     fn serialize_unit(self) -> Result<(), Error> {
-        Ok(())
+        self.write_value("")
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
-        unimplemented!()
+        self.serialize_unit()
     }
 
     fn serialize_unit_variant(
@@ -139,60 +426,78 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
         self.write_value(variant)
     }
 
-    fn serialize_newtype_struct<T>(self, _name: &'static str, _value: &T) -> Result<(), Error>
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<(), Error>
     where
         T: ?Sized + Serialize,
     {
-        unimplemented!()
+        value.serialize(self)
     }
 
+    /// Data-carrying variants are written below the variant name, as in
+    /// `transport.Udp=53`.
     fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
-        _value: &T,
+        variant: &'static str,
+        value: &T,
     ) -> Result<(), Error>
     where
         T: ?Sized + Serialize,
     {
-        unimplemented!()
+        self.check_nested()?;
+        let variant = self.escape_str(variant, true);
+        let len = self.push_key(&variant);
+        value.serialize(&mut *self)?;
+        self.prefix.truncate(len);
+        Ok(())
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
-        Ok(SeqSerializer {
-            serializer: self,
-            first: true,
-        })
+        let group = self.check_nested_seq()?;
+        let prefix_len = self.prefix.len();
+        Ok(SeqSerializer::new(self, prefix_len, group))
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
-        Ok(SeqSerializer {
-            serializer: self,
-            first: true,
-        })
+        let group = self.check_nested_seq()?;
+        let prefix_len = self.prefix.len();
+        Ok(SeqSerializer::new(self, prefix_len, group))
     }
 
     fn serialize_tuple_struct(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleStruct, Error> {
-        unimplemented!()
+        self.serialize_tuple(len)
     }
 
     fn serialize_tuple_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant, Error> {
-        unimplemented!()
+        self.check_nested()?;
+        let variant = self.escape_str(variant, true);
+        let prefix_len = self.push_key(&variant);
+        Ok(SeqSerializer::new(self, prefix_len, false))
     }
 
+    /// Maps are written as nested keys, or as a single value with
+    /// `inline_maps`, as in `weights=a:1,b:2`.
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
-        Ok(self)
+        self.check_nested()?;
+        let prefix_len = self.prefix.len();
+        let inline = (self.options.inline_maps && prefix_len > 0).then(Vec::new);
+        Ok(MapSerializer {
+            serializer: self,
+            prefix_len,
+            inline,
+            empty: true,
+        })
     }
 
     fn serialize_struct(
@@ -200,17 +505,32 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStruct, Error> {
-        Ok(self)
+        self.check_nested()?;
+        let prefix_len = self.prefix.len();
+        Ok(MapSerializer {
+            serializer: self,
+            prefix_len,
+            inline: None,
+            empty: true,
+        })
     }
 
     fn serialize_struct_variant(
         self,
-        name: &'static str,
+        _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
-        len: usize,
+        variant: &'static str,
+        _len: usize,
     ) -> Result<Self::SerializeStructVariant, Error> {
-        self.serialize_struct(name, len)
+        self.check_nested()?;
+        let variant = self.escape_str(variant, true);
+        let prefix_len = self.push_key(&variant);
+        Ok(MapSerializer {
+            serializer: self,
+            prefix_len,
+            inline: None,
+            empty: true,
+        })
     }
 }
 
@@ -223,13 +543,111 @@ impl<'a, W: Write> ser::SerializeSeq for SeqSerializer<'a, W> {
     where
         T: ?Sized + Serialize,
     {
-        if !self.first {
-            self.serializer.write_value(",")?;
-        }
-        value.serialize(&mut *self.serializer)
+        self.element(value)
     }
 
     fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'a, W: Write> SeqSerializer<'a, W> {
+    fn new(serializer: &'a mut Serializer<W>, prefix_len: usize, group: bool) -> Self {
+        SeqSerializer {
+            serializer,
+            elements: Vec::new(),
+            len: 0,
+            indexed: false,
+            group,
+            prefix_len,
+        }
+    }
+
+    /// Captures a scalar element, or a sequence joined with the list delimiter,
+    /// for the joined value. A struct or map element switches the sequence to
+    /// indexed keys instead, writing out the elements captured so far.
+    fn element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        if !self.indexed {
+            let key = self.serializer.key;
+            let result = self.serializer.capture(value, key);
+            let grouped = mem::take(&mut self.serializer.grouped);
+            if self.serializer.capture.is_none() {
+                self.serializer.inner = false;
+            }
+            match result {
+                Err(_) if self.serializer.nested && self.serializer.capture.is_none() => {
+                    self.serializer.nested = false;
+                    self.write_indexed()?;
+                }
+                result => {
+                    self.elements.push((result?, grouped));
+                    self.len += 1;
+                    return Ok(());
+                }
+            }
+        }
+        let len = self.serializer.push_index(self.len);
+        value.serialize(&mut *self.serializer)?;
+        self.serializer.prefix.truncate(len);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Switches to indexed keys, writing out the elements captured so far.
+    fn write_indexed(&mut self) -> Result<(), Error> {
+        self.indexed = true;
+        for (index, (element, _)) in mem::take(&mut self.elements).iter().enumerate() {
+            let len = self.serializer.push_index(index);
+            self.serializer.write_value(element)?;
+            self.serializer.prefix.truncate(len);
+        }
+        Ok(())
+    }
+
+    /// Writes the captured elements out as a single value, or as one line
+    /// each with repeated keys. Elements joined as sequences of their own are
+    /// joined with the secondary delimiter, on a single line.
+    ///
+    /// An empty element can't always be told apart from no elements: a lone
+    /// one is an empty value, as is any one on a line of its own. A value is
+    /// only split on the secondary delimiter if its first element is a
+    /// sequence, so one joined with it can't start with a scalar. Such
+    /// sequences are written as indexed keys instead.
+    fn finish(mut self) -> Result<(), Error> {
+        let capturing = self.serializer.capture.is_some();
+        let grouped = self.elements.iter().any(|(_, group)| *group);
+        let repeat = self.serializer.options.repeat_keys && !capturing && !grouped;
+        let ambiguous = match &self.elements[..] {
+            [(element, _)] => element.is_empty(),
+            [(_, false), ..] if grouped => true,
+            elements => repeat && elements.iter().any(|(element, _)| element.is_empty()),
+        };
+        if !self.indexed {
+            self.serializer.check_delimiters()?;
+            if ambiguous && self.group {
+                self.serializer.nested = true;
+                return Err(self.serializer.unsupported("lone empty element"));
+            } else if ambiguous && !capturing {
+                self.write_indexed()?;
+            } else if repeat && !self.elements.is_empty() {
+                for (element, _) in &self.elements {
+                    let value = self.serializer.escape_list(Some(element));
+                    self.serializer.write_value(value)?;
+                }
+            } else if grouped {
+                let value = self.serializer.join_groups(&self.elements);
+                self.serializer.write_value(value)?;
+            } else {
+                let elements = self.elements.iter().map(|(element, _)| element);
+                let value = self.serializer.escape_list(elements);
+                self.serializer.write_value(value)?;
+            }
+        }
+        if self.group {
+            self.serializer.inner = false;
+            self.serializer.grouped = true;
+        }
+        self.serializer.prefix.truncate(self.prefix_len);
         Ok(())
     }
 }
@@ -242,52 +660,47 @@ impl<'a, W: Write> ser::SerializeTuple for SeqSerializer<'a, W> {
     where
         T: ?Sized + Serialize,
     {
-        if !self.first {
-            self.serializer.write_value(",")?;
-        } else {
-            self.first = false;
-        }
-        value.serialize(&mut *self.serializer)
+        self.element(value)
     }
 
     fn end(self) -> Result<(), Error> {
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a, W: Write> ser::SerializeTupleStruct for &'a mut Serializer<W> {
+impl<'a, W: Write> ser::SerializeTupleStruct for SeqSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<(), Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
     where
         T: ?Sized + Serialize,
     {
-        unimplemented!()
+        self.element(value)
     }
 
     fn end(self) -> Result<(), Error> {
-        unimplemented!()
+        self.finish()
     }
 }
 
-impl<'a, W: Write> ser::SerializeTupleVariant for &'a mut Serializer<W> {
+impl<'a, W: Write> ser::SerializeTupleVariant for SeqSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<(), Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
     where
         T: ?Sized + Serialize,
     {
-        unimplemented!()
+        self.element(value)
     }
 
     fn end(self) -> Result<(), Error> {
-        unimplemented!()
+        self.finish()
     }
 }
 
-impl<'a, W: Write> ser::SerializeMap for &'a mut Serializer<W> {
+impl<'a, W: Write> ser::SerializeMap for MapSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
@@ -295,26 +708,83 @@ impl<'a, W: Write> ser::SerializeMap for &'a mut Serializer<W> {
     where
         T: ?Sized + Serialize,
     {
-        key.serialize(&mut **self)?;
-        self.output
-            .write_all(self.separator.to_string().as_bytes())
-            .map_err(|e| Error::IO(e))
+        let key = self.serializer.capture(key, true)?;
+        self.serializer.push_key(&key);
+        Ok(())
     }
 
+    /// Captures a scalar value of a map written inline. Any other value
+    /// switches the map to nested keys instead, writing out the entries
+    /// captured so far.
     fn serialize_value<T>(&mut self, value: &T) -> Result<(), Error>
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)?;
-        self.output.write_all(&[b'\n']).map_err(|e| Error::IO(e))
+        self.empty = false;
+        if self.inline.is_some() {
+            self.serializer.inner = true;
+            let result = self.serializer.capture(value, false);
+            self.serializer.inner = false;
+            self.serializer.grouped = false;
+            match result {
+                Err(_) if self.serializer.nested => {
+                    self.serializer.nested = false;
+                    self.write_nested()?;
+                }
+                result => {
+                    let key = self.serializer.prefix[self.prefix_len + 1..].to_string();
+                    self.inline
+                        .get_or_insert_with(Vec::new)
+                        .push((key, result?));
+                    self.serializer.prefix.truncate(self.prefix_len);
+                    return Ok(());
+                }
+            }
+        }
+        value.serialize(&mut *self.serializer)?;
+        self.serializer.prefix.truncate(self.prefix_len);
+        Ok(())
     }
 
+    /// Writes the captured entries out as a single value of `key:value`
+    /// entries separated by the list delimiter. A nested map with no entries
+    /// is written as an empty value, which reads back as an empty map where
+    /// no keys at all would be a missing field.
     fn end(self) -> Result<(), Error> {
+        if let Some(entries) = &self.inline {
+            self.serializer.check_delimiters()?;
+            let mut value = String::new();
+            for (i, (key, entry)) in entries.iter().enumerate() {
+                if i > 0 {
+                    value.push_str(&self.serializer.options.list_delimiter);
+                }
+                self.serializer.escape_element(&mut value, key, i == 0);
+                value.push(INLINE_MAP_SEPARATOR);
+                self.serializer.escape_element(&mut value, entry, false);
+            }
+            self.serializer.write_value(value)?;
+        } else if self.empty && self.prefix_len > 0 {
+            self.serializer.write_value("")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W: Write> MapSerializer<'a, W> {
+    /// Switches to nested keys, writing out the entries captured so far.
+    fn write_nested(&mut self) -> Result<(), Error> {
+        let key = self.serializer.prefix.split_off(self.prefix_len);
+        for (entry_key, entry) in self.inline.take().unwrap_or_default() {
+            let len = self.serializer.push_key(&entry_key);
+            self.serializer.write_value(entry)?;
+            self.serializer.prefix.truncate(len);
+        }
+        self.serializer.prefix.push_str(&key);
         Ok(())
     }
 }
 
-impl<'a, W: Write> ser::SerializeStruct for &'a mut Serializer<W> {
+impl<'a, W: Write> ser::SerializeStruct for MapSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
@@ -322,31 +792,43 @@ impl<'a, W: Write> ser::SerializeStruct for &'a mut Serializer<W> {
     where
         T: ?Sized + Serialize,
     {
-        self.write_value(key)?;
-        self.output
-            .write_all(self.separator.to_string().as_bytes())?;
-        value.serialize(&mut **self)?;
-        self.output.write_all(&[b'\n']).map_err(|e| Error::IO(e))
+        self.empty = false;
+        let key = self.serializer.escape_str(key, true);
+        let len = self.serializer.push_key(&key);
+        value.serialize(&mut *self.serializer)?;
+        self.serializer.prefix.truncate(len);
+        Ok(())
     }
 
+    /// A nested struct with no fields is written as an empty value, as an
+    /// empty map is.
     fn end(self) -> Result<(), Error> {
+        if self.empty && self.prefix_len > 0 {
+            self.serializer.write_value("")?;
+        }
         Ok(())
     }
 }
 
-impl<'a, W: Write> ser::SerializeStructVariant for &'a mut Serializer<W> {
+impl<'a, W: Write> ser::SerializeStructVariant for MapSerializer<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T>(&mut self, _key: &'static str, _value: &T) -> Result<(), Error>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
     where
         T: ?Sized + Serialize,
     {
-        unimplemented!()
+        ser::SerializeStruct::serialize_field(self, key, value)
     }
 
+    /// A variant with no fields is written as an empty value below the
+    /// variant name.
     fn end(self) -> Result<(), Error> {
-        unimplemented!()
+        if self.empty {
+            self.serializer.write_value("")?;
+        }
+        self.serializer.prefix.truncate(self.prefix_len);
+        Ok(())
     }
 }
 
@@ -354,6 +836,7 @@ impl<'a, W: Write> ser::SerializeStructVariant for &'a mut Serializer<W> {
 mod tests {
     use super::*;
     use std::io::Cursor;
+    use std::str::from_utf8;
     #[test]
     fn test() {
         #[derive(Serialize, PartialEq, Debug)]
@@ -365,4 +848,471 @@ mod tests {
         to_writer(&mut buf, &t).unwrap();
         assert_eq!(from_utf8(buf.get_ref()).unwrap(), "int=10\n");
     }
+
+    #[test]
+    fn escapes() {
+        use std::collections::BTreeMap;
+        let mut m = BTreeMap::new();
+        m.insert("a=b", "tab\there\nnew\\line\x0c");
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer(&mut buf, &m).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "a\\=b=tab\\there\\nnew\\\\line\\f\n"
+        );
+
+        let mut m = BTreeMap::new();
+        m.insert("#key one: two!", "a b:c=d");
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer(&mut buf, &m).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "\\#key\\ one\\:\\ two\\!=a b\\:c\\=d\n"
+        );
+
+        let mut m = BTreeMap::new();
+        m.insert("padded", "  both  ");
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer(&mut buf, &m).unwrap();
+        assert_eq!(from_utf8(buf.get_ref()).unwrap(), "padded=\\  both  \n");
+    }
+
+    #[test]
+    fn options() {
+        #[derive(Serialize)]
+        struct Test {
+            name: &'static str,
+            int: u32,
+        }
+        let t = Test {
+            name: "a:b^c",
+            int: 1,
+        };
+        let options = PropertiesOptions::new()
+            .separator(':')
+            .escape('^')
+            .line_ending(crate::LineEnding::CrLf);
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer_with(&mut buf, &t, &options).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "name:a^:b^^c\r\nint:1\r\n"
+        );
+    }
+
+    #[test]
+    fn nested_structs() {
+        use std::collections::BTreeMap;
+        #[derive(Serialize)]
+        struct Config {
+            name: &'static str,
+            db: Db,
+            labels: BTreeMap<&'static str, &'static str>,
+            cache: Option<Cache>,
+        }
+        #[derive(Serialize)]
+        struct Db {
+            host: &'static str,
+            pool: Pool,
+        }
+        #[derive(Serialize)]
+        struct Pool {
+            max: u32,
+        }
+        #[derive(Serialize)]
+        struct Cache {
+            ttl: u64,
+        }
+        let mut labels = BTreeMap::new();
+        labels.insert("team", "core");
+        labels.insert("k8s.io/name", "orders");
+        let c = Config {
+            name: "orders",
+            db: Db {
+                host: "db.internal",
+                pool: Pool { max: 10 },
+            },
+            labels,
+            cache: None,
+        };
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer(&mut buf, &c).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "name=orders\n\
+             db.host=db.internal\n\
+             db.pool.max=10\n\
+             labels.k8s\\.io/name=orders\n\
+             labels.team=core\n\
+             cache=\n"
+        );
+        let c = Config {
+            labels: BTreeMap::new(),
+            ..c
+        };
+        assert!(crate::to_string(&c)
+            .unwrap()
+            .ends_with("db.pool.max=10\nlabels=\ncache=\n"));
+
+        #[derive(Serialize)]
+        struct Empty {}
+        #[derive(Serialize)]
+        struct HasEmpty {
+            e: Empty,
+            n: u8,
+        }
+        assert_eq!(
+            crate::to_string(&HasEmpty { e: Empty {}, n: 1 }).unwrap(),
+            "e=\nn=1\n"
+        );
+        assert_eq!(crate::to_string(&Empty {}).unwrap(), "");
+    }
+
+    #[test]
+    fn indexed_sequences() {
+        #[derive(Serialize)]
+        struct Config {
+            servers: Vec<Server>,
+            ports: Vec<u16>,
+        }
+        #[derive(Serialize)]
+        struct Server {
+            host: &'static str,
+            port: u16,
+        }
+        let c = Config {
+            servers: vec![
+                Server {
+                    host: "a",
+                    port: 80,
+                },
+                Server {
+                    host: "b",
+                    port: 81,
+                },
+            ],
+            ports: vec![1, 2],
+        };
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer(&mut buf, &c).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "servers[0].host=a\n\
+             servers[0].port=80\n\
+             servers[1].host=b\n\
+             servers[1].port=81\n\
+             ports=1,2\n"
+        );
+
+        let options = PropertiesOptions::new().index_style(IndexStyle::Dots);
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer_with(&mut buf, &c, &options).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "servers.0.host=a\n\
+             servers.0.port=80\n\
+             servers.1.host=b\n\
+             servers.1.port=81\n\
+             ports=1,2\n"
+        );
+    }
+
+    #[test]
+    fn maps_of_structs() {
+        use std::collections::BTreeMap;
+        #[derive(Serialize)]
+        struct DataSource {
+            url: &'static str,
+            pool: u32,
+        }
+        let mut datasource = BTreeMap::new();
+        datasource.insert("primary", DataSource { url: "a", pool: 10 });
+        datasource.insert("reporting.eu", DataSource { url: "b", pool: 2 });
+        let mut c = BTreeMap::new();
+        c.insert("datasource", datasource);
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer(&mut buf, &c).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "datasource.primary.url=a\n\
+             datasource.primary.pool=10\n\
+             datasource.reporting\\.eu.url=b\n\
+             datasource.reporting\\.eu.pool=2\n"
+        );
+    }
+
+    #[test]
+    fn data_enums() {
+        #[derive(Serialize)]
+        enum Transport {
+            Local,
+            Udp(u16),
+            Pair(&'static str, u16),
+            Tcp { host: &'static str, port: u16 },
+        }
+        #[derive(Serialize)]
+        struct Config {
+            transports: Vec<Transport>,
+            fallback: Transport,
+            name: &'static str,
+        }
+        let c = Config {
+            transports: vec![
+                Transport::Local,
+                Transport::Udp(53),
+                Transport::Pair("localhost", 80),
+            ],
+            fallback: Transport::Tcp {
+                host: "localhost",
+                port: 8080,
+            },
+            name: "orders",
+        };
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer(&mut buf, &c).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "transports[0]=Local\n\
+             transports[1].Udp=53\n\
+             transports[2].Pair=localhost,80\n\
+             fallback.Tcp.host=localhost\n\
+             fallback.Tcp.port=8080\n\
+             name=orders\n"
+        );
+    }
+
+    #[test]
+    fn tagged_enums() {
+        #[derive(Serialize)]
+        #[serde(tag = "type", rename_all = "lowercase")]
+        enum Storage {
+            Memory,
+            S3 { bucket: &'static str },
+        }
+        #[derive(Serialize)]
+        #[serde(tag = "kind", content = "spec")]
+        enum Auth {
+            Token(&'static str),
+            Basic { user: &'static str },
+        }
+        #[derive(Serialize)]
+        struct Config {
+            storage: Storage,
+            backups: Vec<Storage>,
+            auth: Auth,
+            fallback: Auth,
+        }
+        let c = Config {
+            storage: Storage::S3 { bucket: "plugins" },
+            backups: vec![Storage::Memory],
+            auth: Auth::Basic { user: "admin" },
+            fallback: Auth::Token("abc"),
+        };
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer(&mut buf, &c).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "storage.type=s3\n\
+             storage.bucket=plugins\n\
+             backups[0].type=memory\n\
+             auth.kind=Basic\n\
+             auth.spec.user=admin\n\
+             fallback.kind=Token\n\
+             fallback.spec=abc\n"
+        );
+    }
+
+    #[test]
+    fn tuples() {
+        #[derive(Serialize)]
+        struct Point(i32, i32);
+        #[derive(Serialize)]
+        struct Config {
+            origin: (i32, i32),
+            point: Point,
+            rgb: [u8; 3],
+        }
+        let c = Config {
+            origin: (0, -1),
+            point: Point(3, 4),
+            rgb: [255, 128, 0],
+        };
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer(&mut buf, &c).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "origin=0,-1\npoint=3,4\nrgb=255,128,0\n"
+        );
+    }
+
+    #[test]
+    fn bytes() {
+        use crate::BinaryEncoding;
+        #[derive(Serialize)]
+        struct Keys {
+            #[serde(with = "serde_bytes")]
+            secret: Vec<u8>,
+            #[serde(with = "serde_bytes")]
+            empty: &'static [u8],
+            plain: Vec<u8>,
+        }
+        let k = Keys {
+            secret: vec![0xfb, 0xff, 0x00, 0x10],
+            empty: b"",
+            plain: vec![1, 2],
+        };
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer(&mut buf, &k).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "secret=+/8AEA==\nempty=\nplain=1,2\n"
+        );
+
+        let options = PropertiesOptions::new().binary_encoding(BinaryEncoding::Base64UrlSafe);
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer_with(&mut buf, &k, &options).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "secret=-_8AEA==\nempty=\nplain=1,2\n"
+        );
+
+        let options = PropertiesOptions::new().binary_encoding(BinaryEncoding::Hex);
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer_with(&mut buf, &k, &options).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "secret=fbff0010\nempty=\nplain=1,2\n"
+        );
+    }
+
+    #[test]
+    fn repeat_keys() {
+        #[derive(Serialize)]
+        struct Server {
+            host: &'static str,
+        }
+        #[derive(Serialize)]
+        struct Config {
+            allow: Vec<&'static str>,
+            deny: Vec<&'static str>,
+            origin: (i32, i32),
+            servers: Vec<Server>,
+        }
+        let c = Config {
+            allow: vec!["a", "b c"],
+            deny: vec![],
+            origin: (0, -1),
+            servers: vec![Server { host: "x" }],
+        };
+        let options = PropertiesOptions::new().repeat_keys(true);
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        to_writer_with(&mut buf, &c, &options).unwrap();
+        assert_eq!(
+            from_utf8(buf.get_ref()).unwrap(),
+            "allow=a\nallow=b c\ndeny=\norigin=0\norigin=-1\nservers[0].host=x\n"
+        );
+    }
+
+    #[test]
+    fn list_escapes() {
+        #[derive(Serialize)]
+        struct Config {
+            tags: Vec<&'static str>,
+            empty: Vec<&'static str>,
+            blank: Vec<&'static str>,
+            chars: Vec<char>,
+            matrix: Vec<Vec<u8>>,
+        }
+        let c = Config {
+            tags: vec!["[a,b", " c ", ""],
+            empty: vec![],
+            blank: vec![""],
+            chars: vec![',', '='],
+            matrix: vec![vec![1, 2], vec![]],
+        };
+        assert_eq!(
+            crate::to_string(&c).unwrap(),
+            "tags=\\[a\\,b,\\ c\\ ,\nempty=\nblank[0]=\nchars=\\,,\\=\n\
+             matrix[0]=1,2\nmatrix[1]=\n"
+        );
+        let options = PropertiesOptions::new().list_delimiter("||");
+        let c = Config {
+            tags: vec!["a|", "|b"],
+            ..c
+        };
+        assert!(crate::to_string_with(&c, &options)
+            .unwrap()
+            .starts_with("tags=a\\|||\\|b\n"));
+
+        #[derive(Serialize)]
+        struct List {
+            v: Vec<&'static str>,
+        }
+        let list = List { v: vec!["ab", "c"] };
+        let err = |options: PropertiesOptions| {
+            crate::to_string_with(&list, &options)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            err(PropertiesOptions::new().list_delimiter("")),
+            "Unsupported empty list delimiter at key v"
+        );
+        assert_eq!(
+            err(PropertiesOptions::new().list_delimiter("t")),
+            "Unsupported list delimiter starting with an escape letter at key v"
+        );
+        assert_eq!(
+            err(PropertiesOptions::new().secondary_delimiter("u")),
+            "Unsupported list delimiter starting with an escape letter at key v"
+        );
+        #[derive(Serialize)]
+        struct Weights {
+            weights: std::collections::BTreeMap<&'static str, u32>,
+        }
+        let mut weights = std::collections::BTreeMap::new();
+        weights.insert("a", 1);
+        let options = PropertiesOptions::new()
+            .inline_maps(true)
+            .list_delimiter("");
+        assert_eq!(
+            crate::to_string_with(&Weights { weights }, &options)
+                .unwrap_err()
+                .to_string(),
+            "Unsupported empty list delimiter at key weights"
+        );
+    }
+
+    #[test]
+    fn nested_lists_and_inline_maps() {
+        use std::collections::BTreeMap;
+        #[derive(Serialize)]
+        struct Config {
+            matrix: Vec<Vec<u32>>,
+            row: Vec<Vec<u32>>,
+            mixed: (u8, Vec<&'static str>),
+            weights: BTreeMap<&'static str, u32>,
+            groups: BTreeMap<&'static str, Vec<u32>>,
+        }
+        let c = Config {
+            matrix: vec![vec![1, 2], vec![3], vec![]],
+            row: vec![vec![1, 2]],
+            mixed: (1, vec!["a;b", "c"]),
+            weights: vec![("a", 1), ("b:c", 2)].into_iter().collect(),
+            groups: vec![("x", vec![1])].into_iter().collect(),
+        };
+        assert_eq!(
+            crate::to_string(&c).unwrap(),
+            "matrix[0]=1,2\nmatrix[1]=3\nmatrix[2]=\nrow[0]=1,2\nmixed[0]=1\nmixed[1]=a;b,c\n\
+             weights.a=1\nweights.b\\:c=2\ngroups.x=1\n"
+        );
+        let options = PropertiesOptions::new()
+            .secondary_delimiter(";")
+            .inline_maps(true);
+        assert_eq!(
+            crate::to_string_with(&c, &options).unwrap(),
+            "matrix=1,2;3;\nrow=1,2\nmixed[0]=1\nmixed[1]=a\\;b,c\n\
+             weights=a:1,b\\:c:2\ngroups.x=1\n"
+        );
+    }
 }